@@ -0,0 +1,47 @@
+use std::f32::consts::PI;
+use criterion::{criterion_group, criterion_main, Criterion};
+use morse_player::{AudioPlayer, TextAdditions, WaveType};
+use ndarray::Array1;
+
+const FREQUENCY: f32 = 750.0;
+const HARMONICS_COUNT: u32 = 20;
+
+fn render_long_tone(c: &mut Criterion) { // one 2 second dash, 20 harmonics, with the fades and the normalization on top of the harmonics
+    let mut group = c.benchmark_group("render_long_tone");
+    for (name, wave_type) in [("square", WaveType::Square), ("triangle", WaveType::Triangle), ("sawtooth", WaveType::Sawtooth)] {
+        let mut audio_player = AudioPlayer::new_headless();
+        audio_player.set_text(&vec!['T']);
+        audio_player.set_text_additions(TextAdditions::None);
+        audio_player.set_speed(100.0);
+        audio_player.set_frequency(FREQUENCY as i32);
+        audio_player.set_element_units('-', 0, 40).unwrap();
+        audio_player.set_wave_type(wave_type);
+        audio_player.set_harmonics(wave_type, Some(HARMONICS_COUNT));
+        group.bench_function(name, |b| b.iter(|| audio_player.render_samples().unwrap()));
+    }
+    group.finish();
+}
+
+fn per_harmonic_baseline(c: &mut Criterion) { // the harmonics alone as they were summed before, one Array1 and one sin() pass per harmonic
+    let mut group = c.benchmark_group("per_harmonic_baseline");
+    let t_wave = Array1::linspace(0.0, 2.0, 96_000);
+    let square: Vec<(u32, f32)> = (0..HARMONICS_COUNT).map(|harmonic| (2 * harmonic + 1, 1.0 / (2 * harmonic + 1) as f32)).collect();
+    let triangle: Vec<(u32, f32)> = (0..HARMONICS_COUNT).map(|harmonic| (2 * harmonic + 1, (-1.0_f32).powi(harmonic as i32) / ((2 * harmonic + 1).pow(2)) as f32)).collect();
+    let sawtooth: Vec<(u32, f32)> = (1..HARMONICS_COUNT).map(|harmonic| (harmonic, 1.0 / harmonic as f32)).collect();
+    for (name, harmonics) in [("square", square), ("triangle", triangle), ("sawtooth", sawtooth)] {
+        group.bench_function(name, |b| b.iter(|| sum_per_harmonic(&t_wave, &harmonics)));
+    }
+    group.finish();
+}
+
+fn sum_per_harmonic(t_wave: &Array1<f32>, harmonics: &[(u32, f32)]) -> Array1<f32> {
+    let mut wave = Array1::zeros(t_wave.len());
+    for (multiplier, amplitude) in harmonics {
+        let harmonic_wave = (2.0 * PI * FREQUENCY * *multiplier as f32 * t_wave).mapv(f32::sin);
+        wave = wave + harmonic_wave * *amplitude;
+    }
+    wave
+}
+
+criterion_group!(benches, render_long_tone, per_harmonic_baseline);
+criterion_main!(benches);
@@ -1,14 +1,11 @@
 use std::time::Duration;
 
-use morse_player;
-
 #[tokio::main]
 async fn main() {
-    let mut audio_player = morse_player::AudioPlayer::new();
+    let mut audio_player = morse_player::AudioPlayer::new_with_text("Hello world").unwrap();
     audio_player.set_speed(100.0);
-    audio_player.set_text(&vec!['H', 'E', 'L', 'L', 'O', ' ', 'W', 'O', 'R', 'L', 'D'].to_vec());
-    audio_player.set_text_type(morse_player::TextType::Letters);
+    audio_player.set_text_type(morse_player::TextType::Auto);
     audio_player.set_text_additions(morse_player::TextAdditions::None);
-    audio_player.play().await;
+    audio_player.play().await.unwrap();
     tokio::time::sleep(Duration::from_millis(500)).await;
 }
\ No newline at end of file
@@ -0,0 +1,58 @@
+use rustfft::{num_complex::Complex, FftPlanner};
+
+const MIN_TONE_FREQUENCY: f32 = 300.0;
+const MAX_TONE_FREQUENCY: f32 = 1200.0;
+
+pub fn detect_tone_frequency(samples: &[f32], sample_rate: u32) -> f32 { // dominant peak in the CW range, 0.0 if none
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let mut buffer: Vec<Complex<f32>> = samples.iter().map(|sample| Complex::new(*sample, 0.0)).collect();
+    let fft = FftPlanner::<f32>::new().plan_fft_forward(buffer.len());
+    fft.process(&mut buffer);
+
+    let bin_width = sample_rate as f32 / samples.len() as f32;
+    let min_bin = ((MIN_TONE_FREQUENCY / bin_width).ceil() as usize).max(1);
+    let max_bin = ((MAX_TONE_FREQUENCY / bin_width).floor() as usize).min((samples.len() / 2).saturating_sub(1));
+    if min_bin > max_bin {
+        return 0.0;
+    }
+
+    let magnitudes: Vec<f32> = buffer.iter().map(|bin| bin.norm()).collect();
+    let mut peak_bin = min_bin;
+    for bin in min_bin..=max_bin {
+        if magnitudes[bin] > magnitudes[peak_bin] {
+            peak_bin = bin;
+        }
+    }
+
+    // Parabolic interpolation between neighbour bins
+    let (left, peak, right) = (magnitudes[peak_bin - 1], magnitudes[peak_bin], magnitudes[peak_bin + 1]);
+    let denominator = left - 2.0 * peak + right;
+    let offset = if denominator != 0.0 { 0.5 * (left - right) / denominator } else { 0.0 };
+    (peak_bin as f32 + offset) * bin_width
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::morse_player::{AudioPlayer, TextAdditions, SAMPLE_RATE};
+
+    #[test]
+    fn rendered_frequency_is_recovered() {
+        for frequency in [400, 700, 1100] {
+            let mut player = AudioPlayer::new_headless();
+            player.set_text_str("PARIS");
+            player.set_text_additions(TextAdditions::None);
+            player.set_frequency(frequency);
+            let detected = detect_tone_frequency(&player.render_samples().unwrap(), SAMPLE_RATE);
+            assert!((detected - frequency as f32).abs() < 5.0, "{} detected as {}", frequency, detected);
+        }
+    }
+
+    #[test]
+    fn no_tone_gives_zero() {
+        assert_eq!(detect_tone_frequency(&[], SAMPLE_RATE), 0.0);
+        assert_eq!(detect_tone_frequency(&[0.5; 8], SAMPLE_RATE), 0.0);
+    }
+}
@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use rand::distr::{weighted::WeightedIndex, Distribution};
+use crate::morse_player::{gen_rng, MorseError, PlayerSettings};
+
+const GROUP_SIZE: usize = 5;
+
+pub fn weighted_practice(weights: &HashMap<char, f32>, length: usize, seed: Option<u64>) -> String { // length characters in groups of 5, chars with weight 0 never appear, empty if no char has a positive weight
+    let mut chars: Vec<(char, f32)> = weights.iter()
+        .filter(|(_, weight)| weight.is_finite() && **weight > 0.0)
+        .map(|(ch, weight)| (*ch, *weight))
+        .collect();
+    chars.sort_by_key(|(ch, _)| *ch); // same order for the same seed, HashMap order is random
+    let Ok(distribution) = WeightedIndex::new(chars.iter().map(|(_, weight)| *weight)) else {
+        return String::new();
+    };
+    let mut rng = gen_rng(seed);
+
+    let mut practice = String::new();
+    for i in 0..length {
+        if i > 0 && i % GROUP_SIZE == 0 {
+            practice.push(' ');
+        }
+        practice.push(chars[distribution.sample(&mut rng)].0);
+    }
+    practice
+}
+
+pub fn estimate_duration(text: &str, settings: &PlayerSettings) -> Result<Duration, MorseError> { // main text only, start and end parts aren't in the settings
+    Ok(Duration::from_secs_f32(settings.get_text_duration(text)?.max(0.0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_weights_never_appear() {
+        let weights = HashMap::from([('A', 1.0), ('B', 0.0), ('C', 2.0), ('D', f32::NAN)]);
+        let practice = weighted_practice(&weights, 200, Some(7));
+        assert!(practice.chars().all(|ch| ch == 'A' || ch == 'C' || ch == ' '));
+        assert!(practice.contains('A') && practice.contains('C'));
+    }
+
+    #[test]
+    fn all_zero_weights_give_an_empty_text() {
+        let weights = HashMap::from([('A', 0.0), ('B', -1.0)]);
+        assert_eq!(weighted_practice(&weights, 10, Some(7)), "");
+        assert_eq!(weighted_practice(&HashMap::new(), 10, None), "");
+    }
+
+    #[test]
+    fn seed_reproduces_the_text() {
+        let weights = HashMap::from([('A', 1.0), ('B', 1.0), ('C', 1.0), ('D', 1.0)]);
+        let practice = weighted_practice(&weights, 12, Some(42));
+        assert_eq!(practice, weighted_practice(&weights, 12, Some(42)));
+        assert_ne!(practice, weighted_practice(&weights, 12, Some(43)));
+        assert_eq!(practice.split(' ').map(str::len).collect::<Vec<usize>>(), vec![5, 5, 2]);
+    }
+}
@@ -1,7 +1,28 @@
 pub mod morse_player;
+pub mod decoder;
+pub mod generator;
+pub mod scoring;
 
 pub use morse_player::AudioPlayer;
+pub use morse_player::PlaybackHandle;
+pub use morse_player::PlayerPool;
+pub use morse_player::PlayReport;
+pub use morse_player::MorseEvent;
+pub use morse_player::MorseSource;
 pub use morse_player::TextType;
 pub use morse_player::WaveType;
+pub use morse_player::NoiseType;
+pub use morse_player::FadeMode;
+pub use morse_player::UnknownPolicy;
 pub use morse_player::TextAdditions;
-pub use morse_player::SpeedModificationType;
\ No newline at end of file
+pub use morse_player::SpeedModificationType;
+pub use morse_player::BitDepth;
+pub use morse_player::Token;
+pub use morse_player::MorseError;
+pub use morse_player::PlayerSettings;
+pub use morse_player::morse_to_text;
+pub use morse_player::relative_difficulty;
+pub use morse_player::samples_checksum;
+pub use morse_player::play_message;
+pub use scoring::CopyScore;
+pub use scoring::score_copy;
\ No newline at end of file
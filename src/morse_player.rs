@@ -1,16 +1,28 @@
-use std::{collections::HashMap, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}, thread, time::Duration};
+use std::{collections::HashMap, fmt, future::{Future, IntoFuture}, io::{self, Cursor, Write}, ops::{Range, RangeInclusive}, pin::Pin, path::Path, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc, Mutex, MutexGuard, OnceLock, PoisonError}, thread, time::{Duration, Instant}};
 use rodio::{OutputStream, OutputStreamHandle, Sink};
+use midly::{num::{u15, u24, u7}, Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
 use ndarray::Array1;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
 use std::f32::consts::PI;
 use tokio::{self, time::sleep};
 
-const SAMPLE_RATE: u32 = 48000;
+pub(crate) const SAMPLE_RATE: u32 = 48000;
+const MIN_SPEED: f32 = 10.0;
+const MAX_SPEED: f32 = 1000.0;
+const MIN_VOLUME_DB: f32 = -60.0; // and below is silence
 const LETTERS_DURATION: f32 = 0.05;
 const DIGITS_DURATION: f32 = 0.034;
 const MIXED_DURATION: f32 = 0.042;
-const HARMONICS_COUNT: u32 = 20;
+const HARMONICS_COUNT: u32 = 20; // default for every wave type
+const HARMONICS_LANES: usize = 8; // samples synthesized together
+const SINE_TABLE_SIZE: usize = 1024;
+static SINE_TABLE: OnceLock<Vec<f64>> = OnceLock::new();
 const FADE_IN: f32 = 0.0004;
 const FADE_OUT: f32 = 0.0002;
+const KEYING_BANDWIDTH_FACTOR: f32 = 5.0; // ITU K factor for telegraphy
+const MAX_WAVE_DURATION: f32 = 60.0; // seconds, longer tones and pauses are rejected by the element walk to avoid huge allocations
+const RESERVED_ELEMENTS: &[char] = &['|', '~', '#', '^', '<', '>', '%', '!', '+']; // set by the player, set_element_units can't change them
+const LOUDNESS_RMS: f32 = 0.5; // about the RMS of the normalized sawtooth, the quietest wave type
 const START_TEXT: [char; 34] = ['.', '*', '.', '*', '.', '*', '-', '$',
                                 '.', '*', '.', '*', '.', '*', '-', '$',
                                 '.', '*', '.', '*', '.', '*', '-', '/',
@@ -29,11 +41,83 @@ const START_TEXT_COMPETITIONS_DIGITS: [char; 50] = [
 '-', '*', '-', '*', '-', '*', '-', '*', '-', '$',
 '-', '*', '-', '*', '-', '*', '-', '*', '-', '/'
 ];
+const MORSE_CODE: [(char, &str); 50] = [
+    ('A', ".-"), ('B', "-..."), ('C', "-.-."), ('D', "-.."), ('E', "."),
+    ('F', "..-."), ('G', "--."), ('H', "...."), ('I', ".."), ('J', ".---"),
+    ('K', "-.-"), ('L', ".-.."), ('M', "--"), ('N', "-."), ('O', "---"),
+    ('P', ".--."), ('Q', "--.-"), ('R', ".-."), ('S', "..."), ('T', "-"),
+    ('U', "..-"), ('V', "...-"), ('W', ".--"), ('X', "-..-"), ('Y', "-.--"),
+    ('Z', "--.."), ('0', "-----"), ('1', ".----"), ('2', "..---"), ('3', "...--"),
+    ('4', "....-"), ('5', "....."), ('6', "-...."), ('7', "--..."), ('8', "---.."),
+    ('9', "----."), ('.', ".-.-.-"), (',', "--..--"), ('/', "-..-."), ('?', "..--.."),
+    ('=', "-...-"), ('&', ".-..."), ('+', ".-.-."), ('(', "-.--."), (')', "-.--.-"),
+    ('-', "-....-"), ('@', ".--.-."), (':', "---..."), ('\'', ".----."), ('"', ".-..-.")];
+const LETTERS_BY_FREQUENCY: [char; 26] = ['E', 'T', 'A', 'O', 'I', 'N', 'S', 'H', 'R', 'D', 'L', 'C', 'U',
+                                          'M', 'W', 'F', 'G', 'Y', 'P', 'B', 'V', 'K', 'J', 'X', 'Q', 'Z'];
+const ABBREVIATIONS: [&str; 28] = ["CQ", "DE", "QRL", "QRM", "QRN", "QRO", "QRP", "QRQ", "QRS", "QRT", "QRZ", "QSB", "QSL", "QSO",
+                                   "QSY", "QTH", "73", "88", "RST", "TNX", "TU", "OM", "FB", "UR", "PSE", "AGN", "WX", "ANT"];
+const CQ_TEMPLATE: &str = "CQ CQ CQ DE {call} {call} K";
 const END_TEXT: [char; 10] = ['/', '.', '*', '-', '*', '.', '*', '-', '*', '.'];
+const CUT_NUMBERS: [(char, char); 8] = [('1', 'A'), ('2', 'U'), ('3', 'V'), ('5', 'E'), ('7', 'B'), ('8', 'D'), ('9', 'N'), ('0', 'T')];
 const SINK_BUFFER_SIZE: u32 = 3;
+const BUFFER_DURATION: f32 = 0.25; // seconds of audio in one sink buffer, the whole last element goes into it
+const STOP_CHECK_INTERVAL: usize = 16; // elements between stop flag checks while building a buffer
+const DETERMINISTIC_SEED: u64 = 0x4D4F525345; // "MORSE"
+const NO_CHAR: usize = usize::MAX; // current_char when no main text character is sounding
+const MIDI_TICKS_PER_BEAT: u16 = 480;
+const MIDI_TEMPO: u32 = 500000; // microseconds per beat
+const MIDI_VELOCITY: u8 = 100;
+#[cfg(feature = "serial")]
+const SERIAL_BAUD_RATE: u32 = 9600; // only the control lines are used
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MorseError {
+    InvalidElement(char),
+    InvalidAction(i32),
+    InvalidUnits(i32),
+    InvalidFadeFraction(f32), // FadeMode::FractionOfDit outside 0.0..=0.5
+    InvalidDuration(Duration), // zero or longer than MAX_WAVE_DURATION
+    InvalidSpeed(f32), // zero, negative or not finite
+    InvalidBaseDuration(f32), // zero, negative or not finite
+    InvalidFrequency(f32), // not finite or not between 0 and the Nyquist frequency
+    InvalidGain(f32), // negative or not finite
+    InvalidDecibels(f32), // not finite
+    UnsupportedChar(char),
+    NoMorseText, // none of the characters of the text has Morse code, so only the start and end parts would sound
+    LockPoisoned, // a thread panicked while holding the audio output, the next call works again
+    NoOutputDevice, // the default audio output couldn't be opened
+}
+
+impl fmt::Display for MorseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MorseError::InvalidElement(element) => write!(f, "{:?} can't be used as a tone or pause element", element),
+            MorseError::InvalidAction(action) => write!(f, "action {} is not a tone (0) or a pause (1)", action),
+            MorseError::InvalidUnits(units) => write!(f, "element length {} is too short", units),
+            MorseError::InvalidFadeFraction(fraction) => write!(f, "fade of {} dits is not in 0.0..=0.5", fraction),
+            MorseError::InvalidDuration(duration) => write!(f, "duration {:?} is not in 0..={} seconds", duration, MAX_WAVE_DURATION),
+            MorseError::InvalidSpeed(speed) => write!(f, "speed {} is not a positive number", speed),
+            MorseError::InvalidBaseDuration(duration) => write!(f, "base duration {} is not a positive number of seconds", duration),
+            MorseError::InvalidFrequency(frequency) => write!(f, "frequency {} Hz is not between 0 and {} Hz", frequency, SAMPLE_RATE / 2),
+            MorseError::InvalidGain(gain) => write!(f, "gain {} is not a finite number of at least 0", gain),
+            MorseError::InvalidDecibels(db) => write!(f, "{} dB is not a finite level", db),
+            MorseError::UnsupportedChar(ch) => write!(f, "{:?} has no Morse code", ch),
+            MorseError::NoMorseText => write!(f, "no character of the text has Morse code"),
+            MorseError::LockPoisoned => write!(f, "audio output was locked by a thread that panicked, try again"),
+            MorseError::NoOutputDevice => write!(f, "default audio output can't be opened"),
+        }
+    }
+}
+
+impl std::error::Error for MorseError {}
 
 pub type PlayingStartedCallback = Arc<dyn Fn() + 'static>;
 pub type PlayingEndedCallback = Arc<dyn Fn() + 'static>;
+pub type UnderrunCallback = Arc<dyn Fn() + Send + Sync + 'static>;
+pub type RevealCallback = Arc<dyn Fn(usize) + 'static>;
+pub type LookaheadCallback = Arc<dyn Fn(char) + 'static>;
+type EchoWriter = Arc<Mutex<Box<dyn Write + Send>>>;
+pub type EnvelopeFn = Arc<dyn Fn(f32) -> f32 + Send + Sync>; // gain at a position in the tone, 0.0 at its start to 1.0 at its end
 
 #[derive(Clone, Copy)]
 #[derive(PartialEq)]
@@ -41,6 +125,7 @@ pub enum TextType {
     Letters,
     Digits,
     Mixed,
+    Auto, // picks one of the above from the text, explicit variants always override the detection
 }
 
 #[derive(Clone, Copy)]
@@ -61,6 +146,46 @@ pub enum WaveType {
     Sawtooth,
 }
 
+#[derive(Clone, Copy, Default)]
+#[derive(PartialEq)]
+pub enum NoiseType {
+    #[default]
+    White,
+    Pink, // 1/f, closest to band noise
+    Brown, // 1/f^2, a low rumble
+}
+
+#[derive(Clone, Copy)]
+#[derive(PartialEq)]
+pub enum UnknownPolicy { // characters without Morse code in the text
+    Drop,
+    Skip, // dropped, counted in PlayReport::skipped_chars
+    Substitute(char), // sent as this character, dropped if it has no code either
+    Error, // playing, rendering and the timings fail with MorseError::UnsupportedChar
+}
+
+#[derive(Clone, Copy)]
+#[derive(PartialEq)]
+pub enum FadeMode { // length of the tone edges
+    FixedTime, // FADE_IN and FADE_OUT seconds at any speed
+    FractionOfDit(f32), // fade in of this fraction of a dit (0.0..=0.5), the fade out keeps its ratio to the fade in
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    Char(char),
+    Abbreviation(String),
+    Space,
+}
+
+#[derive(Clone, Copy)]
+#[derive(PartialEq)]
+pub enum BitDepth {
+    Int16,
+    Int24,
+    Float32,
+}
+
 #[derive(Clone, Copy)]
 #[derive(PartialEq)]
 pub enum TextAdditions {
@@ -69,6 +194,84 @@ pub enum TextAdditions {
     Competitions
 }
 
+#[derive(Clone)]
+struct TextToPlay {
+    elements: Vec<char>,
+    speed_pattern: Vec<f32>,
+    main_text: Vec<Range<usize>>, // positions of the main text in elements
+    main_chars: Vec<char>, // characters sent in the main text, in order
+    base_duration: f32,
+    skipped_chars: usize, // with UnknownPolicy::Skip
+}
+
+impl TextToPlay {
+    fn is_main_text(&self, position: usize) -> bool {
+        self.main_text.iter().any(|range| range.contains(&position))
+    }
+}
+
+#[derive(Clone)]
+struct SynthSettings {
+    wave_type: WaveType,
+    frequency: i32,
+    pitch_shift: f32, // ratio of the frequency that sounds to the set one
+    binaural_beat: f32, // Hz above the frequency in the right channel when playing, 0.0 for mono
+    frequency_offset: f32, // Hz, binaural_beat in the synthesizer of the right channel
+    marker_volume: f32,
+    loudness_match: bool,
+    lowpass: Option<f32>,
+    smooth_joins: bool,
+    fist_amount: f32,
+    fist_seed: Option<u64>,
+    inverted: bool,
+    output_gain: f32,
+    sine_lookup: bool,
+    dot_gain: f32,
+    dash_gain: f32,
+    noise: Option<(f32, NoiseType)>, // signal to noise ratio in dB
+    phase_continuous: bool,
+    zero_crossing_start: bool,
+    envelope: Option<EnvelopeFn>, // replaces the Hann fades
+    fade_mode: FadeMode,
+    noise_seed: Option<u64>, // only render_samples_deterministic sets it
+    square_harmonics: Option<u32>, // None picks the most harmonics below the Nyquist frequency
+    triangle_harmonics: Option<u32>,
+    sawtooth_harmonics: Option<u32>,
+}
+
+#[derive(Clone)]
+struct GeneratorSettings {
+    code_table: HashMap<char, String>,
+    cut_numbers: bool,
+    cut_number_table: HashMap<char, char>,
+    word_echo: bool,
+    group_gap: bool,
+    emphasis_chars: Vec<char>,
+    emphasis_factor: f32,
+    merge_chars: bool,
+    element_gaps: bool,
+    unknown_policy: UnknownPolicy,
+    word_speeds: HashMap<usize, f32>,
+}
+
+impl Default for GeneratorSettings {
+    fn default() -> Self {
+        GeneratorSettings {
+            code_table: MORSE_CODE.iter().map(|(letter, code)| (*letter, code.to_string())).collect(),
+            cut_numbers: false,
+            cut_number_table: CUT_NUMBERS.iter().cloned().collect(),
+            word_echo: false,
+            group_gap: false,
+            emphasis_chars: Vec::new(),
+            emphasis_factor: 1.0,
+            merge_chars: false,
+            element_gaps: true,
+            unknown_policy: UnknownPolicy::Drop,
+            word_speeds: HashMap::new(),
+        }
+    }
+}
+
 /* 
 
     ADDITIONS:
@@ -77,531 +280,3910 @@ pub enum TextAdditions {
         CompetitionsLetters   competitions start part for letters and mixed (OOOOO/speed/VVV/=) and end part (.-.-.)
         CompetitionsDigits    competitions start part for digits (00000/speed/VVV/=) and end part (.-.-.)
 
+    Start and end parts can be toggled separately with set_start_marker and set_end_marker.
+    With additions set to None an enabled start marker uses the Training start part.
+
 */
 
 pub struct AudioPlayer {
     text: Vec<char>,
     text_type: TextType,
+    letters_duration: f32,
+    digits_duration: f32,
+    mixed_duration: f32,
     speed: f32,
     speed_modification_type: SpeedModificationType,
     min_speed: f32,
     max_speed: f32,
     modification_len: i32,
-    _stream: Arc<Mutex<OutputStream>>,
-    _stream_handle: Arc<Mutex<OutputStreamHandle>>,
+    _stream: Option<OutputStream>, // None when headless
+    _stream_handle: Option<Arc<Mutex<OutputStreamHandle>>>,
     sink: Arc<Mutex<Sink>>,
     stop_flag: Arc<AtomicBool>,
+    producer: Arc<ProducerState>,
+    finish_flag: Arc<AtomicBool>, // set by finish_gracefully, cleared when playing starts
+    current_char: Arc<AtomicUsize>,
     playing_started_callback: Option<PlayingStartedCallback>,
     playing_ended_callback: Option<PlayingEndedCallback>,
+    underrun_callback: Option<UnderrunCallback>,
+    reveal_callback: Option<RevealCallback>,
+    reveal_delay: f32,
+    lookahead_callback: Option<LookaheadCallback>,
+    lookahead: f32,
+    echo_writer: Option<EchoWriter>,
     actions_length: Arc<Mutex<HashMap<char, (i32, i32)>>>,
+    fixed_pauses: FixedPauses,
     text_additions: TextAdditions,
-    wave_type: WaveType,
-    frequency: i32,
+    start_marker: bool,
+    end_marker: bool,
+    synth: SynthSettings,
+    generator: GeneratorSettings,
+    render_char_gap: bool,
+    wav_bit_depth: BitDepth,
+    repeat_times: u32,
+    repeat_markers: bool,
+    last_play_report: Arc<Mutex<Option<PlayReport>>>,
+    abbreviations: Vec<String>,
+    announcement_speed: Option<f32>,
+    announcement_gap: bool,
+    stop_fade: f32,
+    prerender: bool,
+    countdown: u8,
+    preamble: Option<Vec<char>>, // replaces the start additions
+    shuffle_rng: Option<Mutex<StdRng>>, // set while shuffling
+    random_frequency: Option<(RangeInclusive<i32>, Mutex<StdRng>)>, // Hz to pick from on every play
+    periodic_id: Option<(Vec<char>, f32)>, // callsign and seconds of main text between the IDs
+    cq_template: String,
 }
 
 impl AudioPlayer {
-    pub fn new() -> AudioPlayer {
-        let (stream, stream_handle) = OutputStream::try_default().unwrap();
-        let sink = Sink::try_new(&stream_handle).unwrap();
+    pub fn new() -> AudioPlayer { // panics without an output device, try_new returns the error
+        AudioPlayer::try_new().expect("default audio output can't be opened")
+    }
+
+    pub fn try_new() -> Result<AudioPlayer, MorseError> {
+        let (stream, stream_handle) = OutputStream::try_default().map_err(|_| MorseError::NoOutputDevice)?;
+        let sink = Sink::try_new(&stream_handle).map_err(|_| MorseError::NoOutputDevice)?;
+        Ok(AudioPlayer::from_sink(sink, Some(stream), Some(stream_handle)))
+    }
+
+    pub fn new_headless() -> AudioPlayer { // for machines without an output device, where new panics and try_new fails, the audio is consumed in real time and dropped
+        let (sink, output) = Sink::new_idle();
+        thread::spawn(move || drain_in_real_time(output));
+        AudioPlayer::from_sink(sink, None, None)
+    }
+
+    fn from_sink(sink: Sink, stream: Option<OutputStream>, stream_handle: Option<OutputStreamHandle>) -> AudioPlayer {
         sink.set_volume(0.5);
-        let mut m = HashMap::new();
-        m.insert('.', (0, 1));
-        m.insert('-', (0, 3));
-        m.insert('*', (1, 1));
-        m.insert('$', (1, 3));
-        m.insert('/', (1, 7));
-        m.insert('|', (2, 0));
 
         AudioPlayer {text: Vec::<char>::new(), 
             text_type: TextType::Letters, 
+            letters_duration: LETTERS_DURATION,
+            digits_duration: DIGITS_DURATION,
+            mixed_duration: MIXED_DURATION,
             speed: 100.0,
             speed_modification_type: SpeedModificationType::None, 
             min_speed: 100.0, 
             max_speed: 110.0, 
             modification_len: 10,
-            _stream: Arc::new(Mutex::new(stream)), 
-            _stream_handle: Arc::new(Mutex::new(stream_handle)),
+            _stream: stream,
+            _stream_handle: stream_handle.map(|stream_handle| Arc::new(Mutex::new(stream_handle))),
             sink: Arc::new(Mutex::new(sink)),
             stop_flag: Arc::new(AtomicBool::new(false)),
+            producer: Arc::new(ProducerState::default()),
+            finish_flag: Arc::new(AtomicBool::new(false)),
+            current_char: Arc::new(AtomicUsize::new(NO_CHAR)),
             playing_started_callback: None,
             playing_ended_callback: None,
-            actions_length: Arc::new(Mutex::new(m)),
+            underrun_callback: None,
+            reveal_callback: None,
+            echo_writer: None,
+            reveal_delay: 0.0,
+            lookahead_callback: None,
+            lookahead: 0.0,
+            actions_length: Arc::new(Mutex::new(get_default_actions_length())),
+            fixed_pauses: FixedPauses::default(),
             text_additions: TextAdditions::Training,
-            wave_type: WaveType::Square,
-            frequency: 750
+            start_marker: true,
+            end_marker: true,
+            synth: SynthSettings {
+                wave_type: WaveType::Square,
+                frequency: 750,
+                pitch_shift: 1.0,
+                binaural_beat: 0.0,
+                frequency_offset: 0.0,
+                marker_volume: 1.0,
+                loudness_match: true,
+                lowpass: None,
+                smooth_joins: false,
+                fist_amount: 0.0,
+                fist_seed: None,
+                inverted: false,
+                output_gain: 1.0,
+                sine_lookup: false,
+                dot_gain: 1.0,
+                dash_gain: 1.0,
+                noise: None,
+                phase_continuous: false,
+                zero_crossing_start: false,
+                envelope: None,
+                fade_mode: FadeMode::FixedTime,
+                noise_seed: None,
+                square_harmonics: Some(HARMONICS_COUNT),
+                triangle_harmonics: Some(HARMONICS_COUNT),
+                sawtooth_harmonics: Some(HARMONICS_COUNT),
+            },
+            generator: GeneratorSettings::default(),
+            render_char_gap: false,
+            wav_bit_depth: BitDepth::Int16,
+            repeat_times: 1,
+            repeat_markers: false,
+            last_play_report: Arc::new(Mutex::new(None)),
+            abbreviations: ABBREVIATIONS.iter().map(|abbreviation| abbreviation.to_string()).collect(),
+            announcement_speed: None,
+            announcement_gap: false,
+            stop_fade: 0.0,
+            prerender: false,
+            countdown: 0,
+            preamble: None,
+            shuffle_rng: None,
+            random_frequency: None,
+            periodic_id: None,
+            cq_template: CQ_TEMPLATE.to_string(),
         }
     }
 
-    pub fn get_text_duration(&self) -> f32 {
-        let (speed_pattern, text_preview) = gen_audio_prev_vec(&self.text, self.min_speed, self.max_speed, self.speed_modification_type, self.modification_len);
-        let (text_time, _) = get_time_and_timings(&text_preview, self.text_type, self.speed, Some(&speed_pattern), &self.actions_length.lock().unwrap());
-        return text_time
-    }
-
-    pub fn get_start_part_duration(&self) -> f32 {
-        let mut speed: f32 = self.speed;
-        if self.speed_modification_type == SpeedModificationType::Speedup || self.speed_modification_type == SpeedModificationType::Zigzag {
-            speed = self.min_speed;
-        } else if self.speed_modification_type == SpeedModificationType::Slowing {
-            speed = self.max_speed;
+    pub fn new_with_text(text: &str) -> Result<AudioPlayer, MorseError> { // fails on the first character without Morse code or without an output device
+        let mut player = AudioPlayer::try_new()?;
+        player.set_text_str(text);
+        if let Some(ch) = player.text.iter().find(|ch| !ch.is_whitespace() && !player.generator.code_table.contains_key(ch)) {
+            return Err(MorseError::UnsupportedChar(*ch));
         }
-        let start_text: Vec<char> = gen_start_part_prev_vec(self.text_additions, self.text_type, speed);
-        let (text_time, _) = get_time_and_timings(&start_text, self.text_type, speed, None, &self.actions_length.lock().unwrap());
-        return text_time
+        Ok(player)
     }
 
-    pub fn get_char_timings(&self) -> Vec<Duration> {
-        let (speed_pattern, text_preview) = gen_audio_prev_vec(&self.text, self.min_speed, self.max_speed, self.speed_modification_type, self.modification_len);
-        let (_, time_pattern) = get_time_and_timings(&text_preview, self.text_type, self.speed, Some(&speed_pattern), &self.actions_length.lock().unwrap());
-        return time_pattern
+    pub fn get_text_duration(&self) -> Result<f32, MorseError> { // main text only, get_total_duration adds the start and end parts and repeats
+        self.check_unknown_chars(&self.text)?;
+        let (speed_pattern, text_preview) = self.gen_main_text();
+        let (text_time, _) = get_time_and_timings(&text_preview, self.get_base_duration(), self.get_start_speed(), &speed_pattern, &self.get_element_table())?;
+        Ok(text_time)
     }
 
-    pub fn set_text(&mut self, text: &Vec<char>) {
-        self.text = text.to_vec();
+    pub fn get_start_part_duration(&self) -> Result<f32, MorseError> {
+        self.get_start_part_duration_of(self.get_text_type())
     }
 
-    pub fn set_text_type(&mut self, text_type: TextType) {
-        self.text_type = text_type;
+    fn get_start_part_duration_of(&self, text_type: TextType) -> Result<f32, MorseError> {
+        let speed = self.get_start_speed();
+        let (start_speed_pattern, start_text) = self.gen_start_part(text_type);
+        let (text_time, _) = get_time_and_timings(&start_text, self.get_base_duration_of(text_type), speed, &start_speed_pattern, &self.get_element_table())?;
+        Ok(text_time)
     }
 
-    pub fn set_speed(&mut self, speed: f32) {
-        self.speed = speed;
-    }
-    
-    pub fn set_min_speed(&mut self, min_speed: f32) {
-        self.min_speed = min_speed;
+    pub fn get_total_duration(&self) -> Result<f32, MorseError> {
+        self.check_unknown_chars(&self.text)?;
+        self.get_duration_of(&self.gen_text_to_play())
     }
 
-    pub fn set_max_speed(&mut self, max_speed: f32) {
-        self.max_speed = max_speed;
+    fn get_duration_of(&self, text_to_play: &TextToPlay) -> Result<f32, MorseError> {
+        let (total_time, _) = get_time_and_timings(&text_to_play.elements, self.get_base_duration(), self.get_start_speed(), &text_to_play.speed_pattern, &self.get_element_table())?;
+        Ok(total_time)
     }
 
-    pub fn set_modification(&mut self, modification: SpeedModificationType) {
-        self.speed_modification_type = modification;
-    }
-    pub async fn play(&self) {
-        let local = tokio::task::LocalSet::new();
-        let end_notification: Arc<tokio::sync::Notify> = Arc::new(tokio::sync::Notify::new());
-        let text = self.text.clone();
-        let text_type = self.text_type.clone();
-        let mut speed = self.speed;
-        let min_speed = self.min_speed;
-        let max_speed = self.max_speed;
-        let speed_modification_type_ref = self.speed_modification_type;
-        let sink = self.sink.clone();
-        let stop_flag = self.stop_flag.clone();
-        let start_callback = self.playing_started_callback.clone();
-        let end_callback = self.playing_ended_callback.clone();
-        let actions_length = self.actions_length.lock().unwrap().clone();
-        let modification_len = self.modification_len;
-        let additions = self.text_additions;
-        let frequency = self.frequency;
-        let wave_type = self.wave_type;
-    
-        stop_flag.store(false, Ordering::SeqCst);
-        sink.lock().unwrap().play();
-    
-        if self.speed_modification_type == SpeedModificationType::Speedup || self.speed_modification_type == SpeedModificationType::Zigzag {
-            speed = min_speed;
-        } else if self.speed_modification_type == SpeedModificationType::Slowing {
-            speed = max_speed;
-        }
-    
-        let end_notification_ref = Arc::clone(&end_notification);
-        let end_notification_ref2 = Arc::clone(&end_notification);
-        let start_part_duration = self.get_start_part_duration();
-    
-        thread::spawn(move || {
-            let unlocked_sink = sink.lock().unwrap();
-            let mut text_to_play: Vec<char> = Vec::new();
-            let (mode_speed_pattern, text_preview) = gen_audio_prev_vec(
-                &text,
-                min_speed,
-                max_speed,
-                speed_modification_type_ref,
-                modification_len,
-            );
-            text_to_play.extend(gen_start_part_prev_vec(additions, text_type, speed));
-            text_to_play.extend(text_preview);
-            if additions != TextAdditions::None {
-                text_to_play.extend(END_TEXT);
-            }
-            play_audio(
-                &text_to_play,
-                text_type,
-                speed,
-                &unlocked_sink,
-                &stop_flag,
-                &mode_speed_pattern,
-                &actions_length,
-                frequency,
-                wave_type,
-            );
-            end_notification.notify_waiters();
-        });
-    
-        local.spawn_local(async move {
-            if let Some(callback) = start_callback {
-                tokio::select! {
-                    _ = end_notification_ref.notified() => { }
-                    _ = sleep(Duration::from_millis((start_part_duration * 1000.0) as u64)) => callback()
-                }
-            }
-        });
-    
-        local.spawn_local(async move {
-            end_notification_ref2.notified().await;
-            if let Some(callback) = end_callback {
-                callback();
-            }
-        });
-    
-        local.await;
-    }
-    
-    pub fn stop(&self) {
-        self.stop_flag.store(true, Ordering::SeqCst);
-        self.sink.lock().unwrap().clear();
+    pub fn get_char_timings(&self) -> Result<Vec<Duration>, MorseError> {
+        self.check_unknown_chars(&self.text)?;
+        let (speed_pattern, text_preview) = self.gen_main_text();
+        let (_, time_pattern) = get_time_and_timings(&text_preview, self.get_base_duration(), self.get_start_speed(), &speed_pattern, &self.get_element_table())?;
+        Ok(time_pattern)
     }
 
-    pub fn connect_main_text_started_callback<F>(&mut self, callback: F)
-    where
-        F: Fn() + 'static,
-    {
-        self.playing_started_callback = Some(Arc::new(callback));
+    pub fn estimate_bandwidth_hz(&self) -> f32 { // keying bandwidth and the spread from the tone edges at the fastest speed, added in quadrature
+        let fastest_speed = match self.speed_modification_type {
+            SpeedModificationType::None => self.speed,
+            _ => self.min_speed.max(self.max_speed),
+        };
+        let dot_duration = get_dot_duration(self.get_base_duration(), fastest_speed);
+        let keying_bandwidth = KEYING_BANDWIDTH_FACTOR / dot_duration;
+        let (fade_in, fade_out) = get_fade_times(&self.synth, dot_duration);
+        let edge_bandwidth = 1.0 / fade_in.min(fade_out).max(1.0 / SAMPLE_RATE as f32); // an edge is at least one sample
+        (keying_bandwidth.powi(2) + edge_bandwidth.powi(2)).sqrt()
     }
 
-    pub fn connect_playing_ended_callback<F>(&mut self, callback: F)
-    where
-        F: Fn() + 'static,
-    {
-        self.playing_ended_callback = Some(Arc::new(callback));
+    pub fn get_key_timeline(&self) -> Result<Vec<(Duration, Duration)>, MorseError> { // (start, length) of every key-down
+        self.check_unknown_chars(&self.text)?;
+        let text_to_play = self.gen_text_to_play();
+        let key_timeline = get_key_timeline(&text_to_play.elements, self.get_base_duration(), self.get_start_speed(), &text_to_play.speed_pattern, &self.get_element_table(), get_tone_action(&self.synth))?;
+        Ok(key_timeline.iter().map(|(start, length)| (Duration::from_secs_f32(*start), Duration::from_secs_f32(*length))).collect())
     }
 
-    pub fn set_delay(&self, delay: i32) {
-        self.actions_length.lock().unwrap().insert('$', (1, delay));
-        self.actions_length.lock().unwrap().insert('/', (1, (delay as f64 * 2.33) as i32));    
+    pub fn key_timeline_bytes(&self) -> Result<Vec<u8>, MorseError> { // see KEY TIMELINE BYTES
+        self.check_unknown_chars(&self.text)?;
+        let text_to_play = self.gen_text_to_play();
+        let key_timeline = get_key_timeline(&text_to_play.elements, self.get_base_duration(), self.get_start_speed(), &text_to_play.speed_pattern, &self.get_element_table(), get_tone_action(&self.synth))?;
+        let mut bytes = Vec::<u8>::new();
+        let mut cursor_ms: u64 = 0;
+        for (start, length) in key_timeline {
+            let start_ms = (start as f64 * 1000.0).round() as u64; // rounding the edges, not the lengths, so errors don't add up
+            let end_ms = ((start + length) as f64 * 1000.0).round() as u64;
+            if start_ms > cursor_ms {
+                push_varint(&mut bytes, (start_ms - cursor_ms) << 1);
+            }
+            push_varint(&mut bytes, (end_ms.saturating_sub(start_ms) << 1) | 1);
+            cursor_ms = end_ms.max(start_ms);
+        }
+        Ok(bytes)
     }
 
-    pub fn set_modification_length(&mut self, length: i32) {
-        self.modification_len = length;
+    pub fn timeline(&self) -> Result<Vec<MorseEvent>, MorseError> { // every tone and pause of the whole playing
+        self.check_unknown_chars(&self.text)?;
+        let text_to_play = self.gen_text_to_play();
+        get_timeline(&text_to_play, self.get_base_duration(), self.get_start_speed(), &self.get_element_table(), get_tone_action(&self.synth))
     }
 
-    pub fn set_frequency(&mut self, frequency: i32) {
-        self.frequency = frequency;
-    }
-    
-    pub fn set_wave_type(&mut self, wave_type: WaveType) {
-        self.wave_type = wave_type;
+    pub fn char_windows(&self) -> Result<Vec<(usize, Duration, Duration)>, MorseError> { // (char_index, earliest, latest) from the first tone of every main text character to the end of the pause after it, for scoring copied keystrokes
+        Ok(get_char_windows(&self.timeline()?)
+            .iter()
+            .map(|(char_index, earliest, latest)| (*char_index, Duration::from_secs_f32(*earliest), Duration::from_secs_f32(*latest)))
+            .collect())
     }
 
-    pub fn set_volume(&mut self, volume: f32) {
-        self.sink.lock().unwrap().set_volume(volume);
+    #[cfg(feature = "serde")]
+    pub fn timeline_json(&self) -> Result<String, MorseError> { // timeline as a JSON array of events
+        Ok(serde_json::to_string(&self.timeline()?).unwrap_or_default())
     }
 
-    pub fn set_text_additions(&mut self, text_additions: TextAdditions) {
-        self.text_additions = text_additions;
+    #[cfg(feature = "serial")]
+    pub fn key_to_serial(&self, port: &str) -> Result<(), serialport::Error> { // RTS follows the key, blocks until the end or stop
+        let key_timeline = self.get_key_timeline().map_err(|error| serialport::Error::new(serialport::ErrorKind::InvalidInput, error.to_string()))?;
+        let mut port = serialport::new(port, SERIAL_BAUD_RATE).open()?;
+        port.write_request_to_send(false)?;
+        self.stop_flag.store(false, Ordering::SeqCst);
+        let started_at = Instant::now();
+        for (start, length) in key_timeline {
+            if !sleep_until(started_at + start, &self.stop_flag) {
+                break;
+            }
+            port.write_request_to_send(true)?;
+            let completed = sleep_until(started_at + start + length, &self.stop_flag);
+            port.write_request_to_send(false)?;
+            if !completed {
+                break;
+            }
+        }
+        Ok(())
     }
-}
 
-fn apply_hann_window(samples: &mut Array1<f32>, fade_in_samples: usize, fade_out_samples: usize) {
-    let hann_in = Array1::linspace(0.0, PI, fade_in_samples)
-        .mapv(|x| 0.5 * (1.0 - f32::cos(x as f32)));
+    pub fn render_midi(&self, path: &Path, note: u8) -> io::Result<()> {
+        let key = u7::try_from(note).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "MIDI note must be in 0..=127"))?;
+        self.check_morse_text().map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+        self.check_unknown_chars(&self.text).map_err(io::Error::other)?;
+        let text_to_play = self.gen_text_to_play();
+        let key_timeline = get_key_timeline(&text_to_play.elements, self.get_base_duration(), self.get_start_speed(), &text_to_play.speed_pattern, &self.get_element_table(), get_tone_action(&self.synth))
+            .map_err(io::Error::other)?;
+        let ticks_per_second = MIDI_TICKS_PER_BEAT as f32 * 1_000_000.0 / MIDI_TEMPO as f32;
 
-    let hann_out = Array1::linspace(PI, 0.0, fade_out_samples)
-        .mapv(|x| 0.5 * (1.0 - f32::cos(x as f32)));
+        let mut track = vec![TrackEvent {
+            delta: 0.into(),
+            kind: TrackEventKind::Meta(MetaMessage::Tempo(u24::new(MIDI_TEMPO))),
+        }];
+        let mut last_tick: u32 = 0;
+        for (start, length) in key_timeline {
+            let on_tick = (start * ticks_per_second).round() as u32;
+            let off_tick = ((start + length) * ticks_per_second).round() as u32;
+            track.push(TrackEvent {
+                delta: (on_tick - last_tick).into(),
+                kind: TrackEventKind::Midi { channel: 0.into(), message: MidiMessage::NoteOn { key, vel: MIDI_VELOCITY.into() } },
+            });
+            track.push(TrackEvent {
+                delta: (off_tick - on_tick).into(),
+                kind: TrackEventKind::Midi { channel: 0.into(), message: MidiMessage::NoteOff { key, vel: 0.into() } },
+            });
+            last_tick = off_tick;
+        }
+        track.push(TrackEvent { delta: 0.into(), kind: TrackEventKind::Meta(MetaMessage::EndOfTrack) });
 
-    for i in 0..fade_in_samples {
-        samples[i] *= hann_in[i];
+        let smf = Smf {
+            header: Header::new(Format::SingleTrack, Timing::Metrical(u15::new(MIDI_TICKS_PER_BEAT))),
+            tracks: vec![track],
+        };
+        smf.save(path)
     }
 
-    for i in 0..fade_out_samples {
-        let len = samples.len();
-        samples[len - fade_out_samples + i] *= hann_out[i];
+    pub fn render_samples(&self) -> Result<Vec<f32>, MorseError> {
+        self.check_unknown_chars(&self.text)?;
+        self.check_morse_text()?;
+        self.render_elements(self.gen_text_to_play(), self.get_start_speed())
     }
-}
 
-fn get_wave(wave_type: WaveType, frequency: i32, speed_to_use: f32, duration_multiplier: i32) -> Array1::<f32> {
-    let fade_in_samples = (SAMPLE_RATE as f32 * FADE_IN) as usize;
-    let fade_out_samples = (SAMPLE_RATE as f32 * FADE_OUT) as usize;
-    let samples_count_in_dot = SAMPLE_RATE as f32 * speed_to_use;
-    let samples_wave_count = samples_count_in_dot * duration_multiplier as f32;
-    let t_wave = Array1::linspace(0.0, speed_to_use * duration_multiplier as f32, samples_wave_count as usize);
-    let mut wave = match wave_type {
-        WaveType::Square => {
-            let mut wave = Array1::zeros(t_wave.len());
-            for harmonic in 0..HARMONICS_COUNT {
-                let harmonic_frequency = (frequency * (2 * harmonic as i32 + 1)) as f32;
-                let harmonic_wave = (2.0 * PI * harmonic_frequency * &t_wave).mapv(f32::sin);
-                wave = wave + harmonic_wave / (2 * harmonic + 1) as f32;
-            }
-            wave
-        }
-        WaveType::Sine => {
-            (2.0 * PI * frequency as f32 * t_wave).mapv(f32::sin)
-        }
-        WaveType::Triangle => {
-            let mut wave = Array1::zeros(t_wave.len());
-            for harmonic in 0..HARMONICS_COUNT {
-                let harmonic_frequency = (frequency * (2 * harmonic as i32 + 1)) as f32;
-                let harmonic_wave = (2.0 * PI * harmonic_frequency * &t_wave).mapv(f32::sin);
-                let sign = if harmonic % 2 == 0 { 1.0 } else { -1.0 };
-                wave = wave + sign * harmonic_wave / ((2 * harmonic + 1).pow(2)) as f32;
-            }
-            wave
-        }
-        WaveType::Sawtooth => {
-            let mut wave = Array1::zeros(t_wave.len());
-            for harmonic in 1..HARMONICS_COUNT {
-                let harmonic_frequency = (frequency * harmonic as i32) as f32;
-                let harmonic_wave = (2.0 * PI * harmonic_frequency * &t_wave).mapv(f32::sin);
-                wave = wave + harmonic_wave / harmonic as f32;
-            }
-            wave
+    pub fn render_samples_deterministic(&self) -> Result<Vec<f32>, MorseError> { // see DETERMINISTIC RENDERING
+        self.check_unknown_chars(&self.text)?;
+        self.check_morse_text()?;
+        let mut synth = self.synth.clone();
+        synth.fist_seed = synth.fist_seed.or(Some(DETERMINISTIC_SEED));
+        synth.noise_seed = Some(DETERMINISTIC_SEED);
+        let mut synthesizer = self.gen_synthesizer_with(self.gen_text_to_play(), self.get_start_speed(), synth)?;
+        let mut samples = Vec::<f32>::new();
+        while let Some(sound_signal) = synthesizer.next_block() {
+            samples.extend(sound_signal);
         }
-    };
-
-    // Wave normalization
-    let max_amplitude = wave.iter().cloned().fold(f32::MIN, f32::max).abs();
-    if max_amplitude > 0.0 {
-        wave = wave / max_amplitude;
+        Ok(samples)
     }
 
-    apply_hann_window(&mut wave, fade_in_samples, fade_out_samples);
+    pub fn char_sample_offsets(&self) -> Result<Vec<usize>, MorseError> { // where every main text character begins in render_samples, repeats included
+        self.check_unknown_chars(&self.text)?;
+        let text_to_play = self.gen_text_to_play();
+        let (_, char_sample_offsets) = get_samples_and_char_offsets(&text_to_play, self.get_base_duration(), self.get_start_speed(), &self.get_element_table(), &self.synth)?;
+        Ok(char_sample_offsets)
+    }
 
-    wave
-}
+    pub fn rendered_duration(&self) -> Result<Duration, MorseError> { // of render_samples, elements are cut to whole samples, so it's up to a sample per element shorter than get_total_duration (0.1 s over 10 minutes)
+        self.check_unknown_chars(&self.text)?;
+        self.check_morse_text()?;
+        let text_to_play = self.gen_text_to_play();
+        let (samples, _) = get_samples_and_char_offsets(&text_to_play, self.get_base_duration(), self.get_start_speed(), &self.get_element_table(), &self.synth)?;
+        Ok(Duration::from_secs_f64(samples as f64 / SAMPLE_RATE as f64))
+    }
 
-fn get_silence(speed_to_use: f32, duration_multiplier: i32) -> Vec<f32> {
-    let samples_count_in_dot = SAMPLE_RATE as f32 * speed_to_use;
-    let samples_wave_count = samples_count_in_dot * duration_multiplier as f32;
-    let silence: Vec<f32> = vec![0.0; samples_wave_count as usize];
-    silence
-}
+    pub fn dot_samples(&self) -> usize { // at the start speed, as synthesized
+        self.element_samples('.')
+    }
 
-/*
-    DESCRIPTION OF PAUSES:
-        * - Pause beetween dots or dashes
-        $ - Pause beetween characters
-        / - Pause beetween words
+    pub fn dash_samples(&self) -> usize {
+        self.element_samples('-')
+    }
 
-*/
+    pub fn char_gap_samples(&self) -> usize {
+        self.element_samples('$')
+    }
 
-fn play_audio(text: &Vec<char>, text_type: TextType, speed: f32, sink: &Sink, stop_flag: &Arc<AtomicBool>, 
-    speed_pattern: &Vec<f32>, actions_length: &HashMap<char, (i32, i32)>, frequency: i32, wave_type: WaveType) {
-    let mut sound_signal = Vec::<f32>::new();
-    let mut speed_to_use = get_speed_from_text_type(text_type, speed);
-    let mut char_now = 0;
-    let mut short_wave = get_wave(wave_type, frequency, speed_to_use, actions_length.get(&'.').unwrap().1);
-    let mut long_wave = get_wave(wave_type, frequency, speed_to_use, actions_length.get(&'-').unwrap().1);
-    let mut short_silence = get_silence(speed_to_use, actions_length.get(&'*').unwrap().1);
-    let mut medium_silence = get_silence(speed_to_use, actions_length.get(&'$').unwrap().1);
-    let mut long_silence = get_silence(speed_to_use, actions_length.get(&'/').unwrap().1);
+    pub fn word_gap_samples(&self) -> usize {
+        self.element_samples('/')
+    }
 
-    for (i, element) in text.iter().enumerate() {
-        let action_description = actions_length.get(&element);
-        let action: i32 = action_description.unwrap().0;
+    fn element_samples(&self, element: char) -> usize {
+        let speed_to_use = get_dot_duration(self.get_base_duration(), self.get_start_speed());
+        self.get_element_table().get(&element).map_or(0, |action_description| get_samples_count(get_element_duration(action_description, speed_to_use)))
+    }
 
-        if action == 0 {
-            if element == &'.' {
-                sound_signal.extend(short_wave.clone());
-            }
-            else {
-                sound_signal.extend(long_wave.clone());
-            }
+    pub fn render_into(&self, output: &mut dyn FnMut(&[f32])) -> Result<(), MorseError> { // render_samples block by block, mono at 48 kHz, for audio backends other than rodio
+        self.check_unknown_chars(&self.text)?;
+        self.check_morse_text()?;
+        let mut synthesizer = self.gen_synthesizer(self.gen_text_to_play(), self.get_start_speed())?;
+        while let Some(sound_signal) = synthesizer.next_block() {
+            output(&sound_signal);
         }
-        else if action == 1 {
-            if element == &'*' {
-                sound_signal.extend(short_silence.clone());
-            }
-            else if element == &'$' {
-                sound_signal.extend(medium_silence.clone());
+        Ok(())
+    }
+
+    pub fn render_to_wav(&self, path: &Path) -> io::Result<()> {
+        self.check_morse_text().map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+        write_wav(path, &self.render_samples().map_err(io::Error::other)?, self.wav_bit_depth)
+    }
+
+    pub fn to_wav_data_uri(&self) -> io::Result<String> { // render_to_wav in memory, for an <audio src> or a JSON API
+        self.check_morse_text().map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+        let mut wav = Cursor::new(Vec::new());
+        write_wav_to(&mut wav, &self.render_samples().map_err(io::Error::other)?, self.wav_bit_depth)?;
+        Ok(format!("data:audio/wav;base64,{}", encode_base64(wav.get_ref())))
+    }
+
+    pub fn render_chars_to_dir(&self, chars: &[char], dir: &Path) -> io::Result<()> {
+        for ch in chars {
+            let (speed_pattern, mut elements) = self.gen_plain_prev_vec(&[*ch]);
+            if elements.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("no Morse code for {:?}", ch)));
             }
-            else {
-                sound_signal.extend(long_silence.clone());
+            if self.render_char_gap {
+                elements.push('$');
             }
+            let main_text = 0..elements.len();
+            let text_to_play = self.add_pad(TextToPlay { elements, speed_pattern, main_text: vec![main_text], main_chars: vec![*ch], base_duration: self.get_base_duration(), skipped_chars: 0 });
+            write_wav(&dir.join(get_char_file_name(*ch)), &self.render_elements(text_to_play, self.speed).map_err(io::Error::other)?, self.wav_bit_depth)?;
         }
-        else if action == 2 {
-            speed_to_use = get_speed_from_text_type(text_type, speed_pattern[char_now]);
-            short_wave = get_wave(wave_type, frequency, speed_to_use, actions_length.get(&'.').unwrap().1);
-            long_wave = get_wave(wave_type, frequency, speed_to_use, actions_length.get(&'-').unwrap().1);
-            short_silence = get_silence(speed_to_use, actions_length.get(&'*').unwrap().1);
-            medium_silence = get_silence(speed_to_use, actions_length.get(&'$').unwrap().1);
-            long_silence = get_silence(speed_to_use, actions_length.get(&'/').unwrap().1); 
-            char_now += 1;
-        }
+        Ok(())
+    }
 
-        if *element == '/' || i+1 == text.len() {
-            loop {
-                if sink.len() <= SINK_BUFFER_SIZE as usize {
-                    break;
-                }
-                if stop_flag.load(Ordering::SeqCst) {
-                    return;
-                }
-                std::thread::sleep(Duration::from_millis(5));
-            }
-            sink.append(rodio::buffer::SamplesBuffer::new(1, SAMPLE_RATE, sound_signal.to_vec()));
-            sound_signal.clear();
+    fn render_elements(&self, text_to_play: TextToPlay, speed: f32) -> Result<Vec<f32>, MorseError> {
+        let mut synthesizer = self.gen_synthesizer(text_to_play, speed)?;
+        let mut samples = Vec::<f32>::new();
+        while let Some(sound_signal) = synthesizer.next_block() {
+            samples.extend(sound_signal);
         }
+        Ok(samples)
     }
 
-    while sink.len() != 0 {
-        if stop_flag.load(Ordering::SeqCst) {
-            return;
-        }
-        std::thread::sleep(Duration::from_millis(5));
+    fn gen_synthesizer(&self, text_to_play: TextToPlay, speed: f32) -> Result<Synthesizer, MorseError> {
+        self.gen_synthesizer_with(text_to_play, speed, self.synth.clone())
     }
-}
 
-fn gen_start_part_prev_vec(text_additions: TextAdditions, text_type: TextType, speed: f32) -> Vec<char> {
-    let mut start_part: Vec<char> = Vec::new();
-    let mut speed_chars_vec: Vec<char> = Vec::new();
-    let speed_str = (speed.round() as i32).to_string();
-    for ch in speed_str.chars() {
-        speed_chars_vec.push(ch);
+    fn gen_synthesizer_with(&self, text_to_play: TextToPlay, speed: f32, synth: SynthSettings) -> Result<Synthesizer, MorseError> {
+        let actions_length = self.get_element_table();
+        let base_duration = text_to_play.base_duration;
+        Synthesizer::new(text_to_play, base_duration, speed, actions_length, synth)
     }
-    match text_additions {
-        TextAdditions::None => {
 
-        },
-        TextAdditions::Training => {
-            start_part.extend(START_TEXT);
+    fn gen_play_synth(&self) -> SynthSettings { // the synth settings of one play, with the random frequency picked
+        let mut synth = self.synth.clone();
+        if let Some((range, rng)) = &self.random_frequency {
+            synth.frequency = rng.lock().unwrap_or_else(PoisonError::into_inner).random_range(range.clone());
         }
-        TextAdditions::Competitions => {
-            if text_type == TextType::Digits {
-                start_part.extend(START_TEXT_COMPETITIONS_DIGITS);
-                start_part.extend(gen_audio_prev_vec(&speed_chars_vec, 100.0, 100.0, SpeedModificationType::None, 10).1);
-                start_part.push('/');
-                start_part.extend(START_TEXT);
-            }
-            else {
-                start_part.extend(START_TEXT_COMPETITIONS_LETTERS);
-                start_part.extend(gen_audio_prev_vec(&speed_chars_vec, 100.0, 100.0, SpeedModificationType::None, 10).1);
-                start_part.push('/');
-                start_part.extend(START_TEXT);
-            }
-        },
+        synth
     }
-    start_part
-}
 
-fn gen_audio_prev_vec(text: &Vec<char>, min_speed: f32, max_speed: f32, speed_modification_type: SpeedModificationType, modification_len: i32) -> (Vec<f32>, Vec<char>) {
-    let morse: HashMap<char, &str> = [
-        ('A', ".-"), ('B', "-..."), ('C', "-.-."), ('D', "-.."), ('E', "."),
-        ('F', "..-."), ('G', "--."), ('H', "...."), ('I', ".."), ('J', ".---"),
-        ('K', "-.-"), ('L', ".-.."), ('M', "--"), ('N', "-."), ('O', "---"),
-        ('P', ".--."), ('Q', "--.-"), ('R', ".-."), ('S', "..."), ('T', "-"),
-        ('U', "..-"), ('V', "...-"), ('W', ".--"), ('X', "-..-"), ('Y', "-.--"),
-        ('Z', "--.."), ('0', "-----"), ('1', ".----"), ('2', "..---"), ('3', "...--"),
-        ('4', "....-"), ('5', "....."), ('6', "-...."), ('7', "--..."), ('8', "---.."),
-        ('9', "----."), ('.', ".-.-.-"), (',', "--..--"), ('/', "-..-."), ('?', "..--.."),
-        ('=', "-...-")].iter().cloned().collect();
-    let mut audio_vec = Vec::<char>::new();
-    let mut speed_pattern = Vec::<f32>::new();
-    let speed_difference = max_speed - min_speed;
-    let modification_len = modification_len * 5;
-    let mut char_now: i32 = 0;
+    fn gen_binaural_synthesizers(&self, text_to_play: TextToPlay, speed: f32) -> Result<(Synthesizer, Option<Synthesizer>), MorseError> { // left and right, no right one without a beat
+        let mut synth = self.gen_play_synth();
+        if synth.binaural_beat == 0.0 {
+            return Ok((self.gen_synthesizer_with(text_to_play, speed, synth)?, None));
+        }
+        let actions_length = self.get_element_table();
+        let base_duration = text_to_play.base_duration;
+        synth.fist_seed = Some(synth.fist_seed.unwrap_or_else(rand::random)); // same loudness changes in both ears
+        let mut right_synth = synth.clone();
+        right_synth.frequency_offset = synth.binaural_beat;
+        Ok((Synthesizer::new(text_to_play.clone(), base_duration, speed, actions_length.clone(), synth)?,
+            Some(Synthesizer::new(text_to_play, base_duration, speed, actions_length, right_synth)?)))
+    }
 
-    for (i, element) in text.iter().enumerate() {
-        if *element != ' ' && speed_modification_type != SpeedModificationType::None {
-            let speed_on_char: f32 = match speed_modification_type {
-                SpeedModificationType::Speedup => {
-                    let speed_on_char = speed_difference / (modification_len - 1) as f32 * char_now as f32 + min_speed;
-                    speed_on_char
-                }
-                SpeedModificationType::Slowing => {
-                    let speed_on_char = max_speed - (speed_difference / (modification_len - 1) as f32 * char_now as f32);
-                    speed_on_char
-                }
-                SpeedModificationType::Zigzag => {
-                    let speed_on_char: f32;
-                    if char_now < modification_len / 2 {
-                        speed_on_char = speed_difference / ((modification_len / 2) - 1) as f32 * char_now as f32 + min_speed;
-                    }
-                    else {
-                        speed_on_char = max_speed - (speed_difference / ((modification_len / 2) - 1) as f32 * (char_now - modification_len / 2) as f32);
-                    }
-                    speed_on_char
-                }
-                _ => {
-                    panic!("Invalid Modification type");
-                },
-            };
+    pub fn element_count(&self) -> usize { // tones and pauses of the main text
+        let (_, text_preview) = self.gen_main_text();
+        count_elements(&text_preview, &self.get_element_table())
+    }
 
-            speed_pattern.push(speed_on_char);
+    pub fn dit_count(&self) -> usize { // length of the main text in dot units
+        let (_, text_preview) = self.gen_main_text();
+        count_dits(&text_preview, &self.get_element_table())
+    }
 
-            char_now += 1;
-            if char_now == modification_len {
-                char_now = 0;
-            }
+    pub fn get_settings(&self) -> PlayerSettings {
+        PlayerSettings {
+            speed: self.speed,
+            speed_modification_type: self.speed_modification_type,
+            min_speed: self.min_speed,
+            max_speed: self.max_speed,
+            modification_len: self.modification_len,
+            actions_length: self.get_element_table(),
+            generator: self.generator.clone(),
+            text_type: self.text_type,
+            letters_duration: self.letters_duration,
+            digits_duration: self.digits_duration,
+            mixed_duration: self.mixed_duration,
+        }
+    }
 
-            audio_vec.push('|'); // char, that inform play function to recalculate speed
+    pub fn would_clip(&self) -> Result<bool, MorseError> { // peak of a probe dash with the loudest gains and the noise, sink volume included
+        let tone_gain = self.synth.marker_volume.max(1.0) * self.synth.dot_gain.max(self.synth.dash_gain).max(1.0) * (1.0 + self.synth.fist_amount);
+        let mut probe: Vec<f32> = get_wave(&self.synth, get_dot_duration(self.get_base_duration(), self.get_start_speed()), 3).iter().map(|sample| sample * tone_gain).collect();
+        if let Some((snr_db, noise_type)) = self.synth.noise { // the same order as the synthesizer, noise before the filter
+            Noise::new(snr_db, noise_type, Some(DETERMINISTIC_SEED)).process(&mut probe);
         }
-        if let Some(morse_code) = morse.get(&element) {
-            for (n, morse_char) in morse_code.chars().enumerate() {
-                audio_vec.push(morse_char);
-                if n+1 != morse_code.len() {
-                    audio_vec.push('*');
-                }
-            }
+        if let Some(cutoff_hz) = self.synth.lowpass {
+            Biquad::lowpass(cutoff_hz, SAMPLE_RATE).process(&mut probe);
         }
+        let peak = probe.iter().fold(0.0_f32, |peak, sample| peak.max(sample.abs()));
+        Ok(peak * self.synth.output_gain * lock_sink(&self.sink)?.volume() > 1.0)
+    }
 
-        if *element != ' ' && i != text.len() - 1 {
-            audio_vec.push('$');
-        }
-        else if *element == ' ' {
-            let audio_vec_len = audio_vec.len();
-            if char_now == 0 && speed_modification_type != SpeedModificationType::None { // if enabled modification, make latest silence long
-                speed_pattern.push(min_speed);
-                audio_vec[audio_vec_len - 1] = '|';
-                audio_vec.push('/');
+    pub fn render_announcement(&self) -> Vec<char> { // speed number of the competitions start part, as elements
+        gen_speed_announcement(self.get_start_speed(), self.announcement_gap)
+    }
+
+    pub fn tokenize(&self) -> Vec<Token> { // words from the abbreviations list become one token, whitespace runs one Space
+        let text: String = self.text.iter().collect();
+        let mut tokens = Vec::<Token>::new();
+        for (i, word) in text.split_whitespace().enumerate() {
+            if i > 0 {
+                tokens.push(Token::Space);
             }
-            else {
-                audio_vec[audio_vec_len - 1] = '/';
+            if self.abbreviations.iter().any(|abbreviation| abbreviation.eq_ignore_ascii_case(word)) {
+                tokens.push(Token::Abbreviation(word.to_string()));
+            } else {
+                tokens.extend(word.chars().map(Token::Char));
             }
         }
+        tokens
     }
 
-    return (speed_pattern, audio_vec);
-}
-
-fn get_speed_from_text_type(text_type: TextType, speed: f32) -> f32 { // calculating absolute speed of text
-    let speed_to_use;
-    match text_type {
-        TextType::Letters => {
-            speed_to_use = LETTERS_DURATION * 100.0 / speed;
-        }
-        TextType::Digits => {
-            speed_to_use = DIGITS_DURATION * 100.0 / speed;
-        }
-        TextType::Mixed => {
-            speed_to_use = MIXED_DURATION * 100.0 / speed;
-        }
+    pub fn set_abbreviations(&mut self, abbreviations: &[&str]) {
+        self.abbreviations = abbreviations.iter().map(|abbreviation| abbreviation.to_string()).collect();
     }
-    speed_to_use
-}
 
-fn get_time_and_timings(audio_prev_vec: &Vec<char>, text_type: TextType, speed: f32, speed_pattern: Option<&Vec<f32>>, actions_length: &HashMap<char, (i32, i32)>) -> (f32, Vec<Duration>) {
-    let mut time_pattern_vec = Vec::<Duration>::new();
-    let mut duration: f32 = 0.0;
-    let mut char_now = 0;
-    let mut speed_to_use: f32 = get_speed_from_text_type(text_type, speed);
-    time_pattern_vec.push(Duration::from_millis(0));
+    pub fn supported_chars(&self) -> Vec<char> {
+        let mut chars: Vec<char> = self.generator.code_table.keys().cloned().collect();
+        chars.sort();
+        chars
+    }
 
-    for element in audio_prev_vec {
-        let action_discription = actions_length.get(&element);
-        let duration_multiplier = action_discription.unwrap().1;
-        duration += speed_to_use * duration_multiplier as f32;
+    #[allow(clippy::ptr_arg)] // the public signature stays &Vec<char>
+    pub fn set_text(&mut self, text: &Vec<char>) {
+        self.text = text.to_vec();
+    }
+
+    pub fn set_text_str<S: AsRef<str>>(&mut self, text: S) { // letters are uppercased to match the code table
+        self.text = text.as_ref().chars().flat_map(|ch| ch.to_uppercase()).collect();
+    }
+
+    pub fn set_text_type(&mut self, text_type: TextType) {
+        self.text_type = text_type;
+    }
+
+    pub fn set_base_durations(&mut self, letters: f32, digits: f32, mixed: f32) -> Result<(), MorseError> { // dot length in seconds at speed 100
+        if let Some(duration) = [letters, digits, mixed].into_iter().find(|duration| !duration.is_finite() || *duration <= 0.0) {
+            return Err(MorseError::InvalidBaseDuration(duration));
+        }
+        self.letters_duration = letters;
+        self.digits_duration = digits;
+        self.mixed_duration = mixed;
+        Ok(())
+    }
+
+    fn get_base_duration(&self) -> f32 {
+        self.get_base_duration_of(self.get_text_type())
+    }
+
+    fn get_base_duration_of(&self, text_type: TextType) -> f32 {
+        get_base_duration_for(text_type, self.letters_duration, self.digits_duration, self.mixed_duration)
+    }
+
+    fn get_text_type(&self) -> TextType { // resolves TextType::Auto
+        self.get_text_type_of(&self.text)
+    }
+
+    fn get_text_type_of(&self, text: &[char]) -> TextType {
+        get_text_type_for(self.text_type, text)
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+    
+    pub fn set_speed_for_duration(&mut self, target: Duration) -> f32 { // everything except fixed pauses scales with 1 / speed
+        let text_to_play = self.gen_text_to_play();
+        let actions_length = self.get_element_table();
+        let fixed_duration: f32 = text_to_play.elements.iter()
+            .filter_map(|element| actions_length.get(element))
+            .filter(|action_description| action_description.0 == 3)
+            .map(|action_description| get_element_duration(action_description, 0.0))
+            .sum();
+        let Ok(duration) = self.get_duration_of(&text_to_play) else {
+            return self.speed;
+        };
+        let scaled_duration = duration - fixed_duration;
+        let target_duration = target.as_secs_f32() - fixed_duration;
+        if scaled_duration <= 0.0 || target_duration <= 0.0 {
+            return self.speed;
+        }
+
+        let speed = (self.speed * scaled_duration / target_duration).clamp(MIN_SPEED, MAX_SPEED);
+        let factor = speed / self.speed;
+        self.speed = speed;
+        self.min_speed *= factor;
+        self.max_speed *= factor;
+        speed
+    }
+
+    pub fn set_min_speed(&mut self, min_speed: f32) {
+        self.min_speed = min_speed;
+    }
+
+    pub fn set_max_speed(&mut self, max_speed: f32) {
+        self.max_speed = max_speed;
+    }
+
+    pub fn set_modification(&mut self, modification: SpeedModificationType) {
+        self.speed_modification_type = modification;
+    }
+
+    fn get_start_speed(&self) -> f32 { // speed of the start part, modifications start from their edge speed
+        get_start_speed_for(self.speed_modification_type, self.speed, self.min_speed, self.max_speed)
+    }
+
+    fn get_start_additions(&self) -> TextAdditions {
+        if !self.start_marker {
+            TextAdditions::None
+        } else if self.text_additions == TextAdditions::None {
+            TextAdditions::Training
+        } else {
+            self.text_additions
+        }
+    }
+
+    fn gen_main_text(&self) -> (Vec<f32>, Vec<char>) {
+        gen_audio_prev_vec(&self.text, self.speed, self.min_speed, self.max_speed, self.speed_modification_type, self.modification_len, &self.generator)
+    }
+
+    fn gen_start_part(&self, text_type: TextType) -> (Vec<f32>, Vec<char>) { // with the countdown and the pre-text gap
+        let (mut speed_pattern, mut start_part) = gen_countdown(self.countdown, self.get_start_speed(), self.announcement_speed);
+        let (additions_speed_pattern, additions) = match &self.preamble {
+            Some(preamble) if self.get_start_additions() != TextAdditions::None => self.gen_preamble(preamble),
+            _ => gen_start_part_prev_vec(self.get_start_additions(), text_type, self.get_start_speed(), self.announcement_speed, self.announcement_gap),
+        };
+        speed_pattern.extend(additions_speed_pattern);
+        start_part.extend(additions);
+        if !start_part.is_empty() {
+            start_part.push('^');
+        }
+        (speed_pattern, start_part)
+    }
+
+    fn gen_preamble(&self, preamble: &[char]) -> (Vec<f32>, Vec<char>) { // with the word gap after it
+        let (_, elements) = self.gen_plain_prev_vec(preamble);
+        let (speed_pattern, mut elements) = gen_at_announcement_speed(elements, self.get_start_speed(), self.announcement_speed);
+        elements.push('/');
+        (speed_pattern, elements)
+    }
+
+    fn gen_text_to_play(&self) -> TextToPlay {
+        self.gen_text_to_play_of(&self.text)
+    }
+
+    fn gen_text_to_play_of(&self, text: &[char]) -> TextToPlay { // text with the player settings
+        let mut elements: Vec<char> = Vec::new();
+        let mut speed_pattern: Vec<f32> = Vec::new();
+        let mut main_text: Vec<Range<usize>> = Vec::new();
+        let text_type = self.get_text_type_of(text);
+        let (text_speed_pattern, text_preview) = gen_audio_prev_vec(text, self.speed, self.min_speed, self.max_speed, self.speed_modification_type, self.modification_len, &self.generator);
+        let (start_speed_pattern, start_part) = self.gen_start_part(text_type);
+        let repeat_times = self.repeat_times.max(1);
+        let text_chars = get_sent_chars(text, &self.generator);
+        let mut main_chars: Vec<char> = Vec::new();
+
+        for iteration in 0..repeat_times {
+            if iteration > 0 {
+                elements.push('~');
+                if self.repeat_markers && self.speed_modification_type != SpeedModificationType::None { // start part again at its own speed
+                    elements.push('|');
+                    speed_pattern.push(self.get_start_speed());
+                }
+            }
+            if iteration == 0 || self.repeat_markers {
+                elements.extend(&start_part);
+                speed_pattern.extend(&start_speed_pattern);
+            }
+            main_text.push(elements.len()..elements.len() + text_preview.len());
+            elements.extend(&text_preview);
+            speed_pattern.extend(&text_speed_pattern);
+            main_chars.extend(&text_chars);
+            if self.end_marker && (iteration + 1 == repeat_times || self.repeat_markers) {
+                elements.extend(END_TEXT);
+            }
+        }
+        let skipped_chars = self.count_skipped_chars(text) * repeat_times as usize;
+        let text_to_play = TextToPlay { elements, speed_pattern, main_text, main_chars, base_duration: self.get_base_duration_of(text_type), skipped_chars };
+        let text_to_play = match &self.periodic_id {
+            Some((callsign, interval)) => {
+                let (_, id) = self.gen_plain_prev_vec(callsign);
+                splice_periodic_id(text_to_play, &id, self.speed, *interval, self.get_start_speed(), &self.get_element_table())
+            }
+            None => text_to_play,
+        };
+        self.add_pad(text_to_play)
+    }
+
+    fn gen_plain_prev_vec(&self, text: &[char]) -> (Vec<f32>, Vec<char>) { // at the base speed with the code table only, without echo, emphasis, word speeds or cut numbers
+        let generator = GeneratorSettings { code_table: self.generator.code_table.clone(), ..GeneratorSettings::default() };
+        gen_audio_prev_vec(text, self.speed, self.speed, self.speed, SpeedModificationType::None, self.modification_len, &generator)
+    }
+
+    fn add_pad(&self, mut text_to_play: TextToPlay) -> TextToPlay { // leading and trailing silence, only if set so the elements stay the same without it
+        if self.get_leading_pad() > 0.0 {
+            text_to_play.elements.insert(0, '<');
+            for range in text_to_play.main_text.iter_mut() {
+                *range = range.start + 1..range.end + 1;
+            }
+        }
+        if self.fixed_pauses.trailing_pad > 0 {
+            text_to_play.elements.push('>');
+        }
+        text_to_play
+    }
+
+    fn get_leading_pad(&self) -> f32 {
+        self.fixed_pauses.leading_pad as f32 / 1000.0
+    }
+
+    fn check_morse_text(&self) -> Result<(), MorseError> { // the start and end parts alone aren't rendered
+        if get_sent_chars(&self.text, &self.generator).is_empty() {
+            return Err(MorseError::NoMorseText);
+        }
+        Ok(())
+    }
+
+    fn count_skipped_chars(&self, text: &[char]) -> usize {
+        match self.generator.unknown_policy {
+            UnknownPolicy::Skip => get_unknown_chars(text, &self.generator).len(),
+            _ => 0,
+        }
+    }
+
+    fn check_unknown_chars(&self, text: &[char]) -> Result<(), MorseError> {
+        match get_unknown_chars(text, &self.generator).first() {
+            Some(ch) if self.generator.unknown_policy == UnknownPolicy::Error => Err(MorseError::UnsupportedChar(*ch)),
+            _ => Ok(()),
+        }
+    }
+
+    pub async fn play(&self) -> Result<(), MorseError> {
+        self.stop_producer().await;
+        self.spawn_play()?.await;
+        Ok(())
+    }
+
+    pub fn spawn_play(&self) -> Result<PlaybackHandle, MorseError> {
+        if let Some(shuffle_rng) = &self.shuffle_rng {
+            let text: String = self.text.iter().collect();
+            let mut words: Vec<&str> = text.split_whitespace().collect();
+            words.shuffle(&mut *shuffle_rng.lock().unwrap_or_else(PoisonError::into_inner));
+            return self.spawn_text(&words.join(" ").chars().collect::<Vec<char>>());
+        }
+        self.check_unknown_chars(&self.text)?;
+        self.spawn_text_to_play(self.gen_text_to_play(), self.get_start_speed(), self.get_leading_pad() + self.get_start_part_duration()?)
+    }
+
+    pub async fn play_sequence(&self, items: &[String], gap: Duration) -> Result<(), MorseError> { // every item as a text with the player settings, stop ends the whole sequence
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 && !self.wait_unless_stopped(gap).await {
+                return Ok(());
+            }
+            let text: Vec<char> = item.chars().collect();
+            self.stop_producer().await;
+            self.spawn_text(&text)?.await;
+            if self.stop_flag.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn call_cq(&self, callsign: &str, repeats: Option<u32>) -> Result<(), MorseError> { // the CQ template with {call} replaced, repeats times with a word gap between or until stop for None
+        let text: Vec<char> = self.cq_template.replace("{call}", callsign.trim()).chars().collect();
+        let word_gap = Duration::from_secs_f64(self.word_gap_samples() as f64 / SAMPLE_RATE as f64);
+        let mut played = 0;
+        while repeats.is_none_or(|repeats| played < repeats) {
+            if played > 0 && !self.wait_unless_stopped(word_gap).await {
+                return Ok(());
+            }
+            self.stop_producer().await;
+            self.spawn_text(&text)?.await;
+            if self.stop_flag.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            played += 1;
+        }
+        Ok(())
+    }
+
+    async fn stop_producer(&self) { // stops the previous playing and awaits the exit of its audio thread, so spawning doesn't block
+        if self.producer.is_running() {
+            self.stop_flag.store(true, Ordering::SeqCst);
+            self.producer.wait_async().await;
+        }
+    }
+
+    async fn wait_unless_stopped(&self, duration: Duration) -> bool { // false if stopped meanwhile
+        let end = Instant::now() + duration;
+        while Instant::now() < end {
+            if self.stop_flag.load(Ordering::SeqCst) {
+                return false;
+            }
+            sleep(Duration::from_millis(5)).await;
+        }
+        true
+    }
+
+    pub fn interrupt_with(&self, text: &[char]) -> Result<PlaybackHandle, MorseError> { // stops the current playing, waits for its audio thread and plays text with the player settings
+        self.spawn_text(text)
+    }
+
+    fn spawn_text(&self, text: &[char]) -> Result<PlaybackHandle, MorseError> { // not the text of the player
+        let text: Vec<char> = text.iter().flat_map(|ch| ch.to_uppercase()).collect();
+        self.check_unknown_chars(&text)?;
+        let start_part_duration = self.get_leading_pad() + self.get_start_part_duration_of(self.get_text_type_of(&text))?;
+        self.spawn_text_to_play(self.gen_text_to_play_of(&text), self.get_start_speed(), start_part_duration)
+    }
+
+    pub async fn play_single(&self, ch: char) -> Result<(), MorseError> { // one character without additions and trailing gap, at the base speed
+        let chars: Vec<char> = ch.to_uppercase().collect();
+        self.check_unknown_chars(&chars)?;
+        let (speed_pattern, elements) = self.gen_plain_prev_vec(&chars);
+        let main_text = 0..elements.len();
+        let main_chars = get_sent_chars(&chars, &self.generator);
+        let skipped_chars = self.count_skipped_chars(&chars);
+        let text_to_play = TextToPlay { elements, speed_pattern, main_text: vec![main_text], main_chars, base_duration: self.get_base_duration(), skipped_chars };
+        self.stop_producer().await;
+        self.spawn_text_to_play(text_to_play, self.speed, 0.0)?.await;
+        Ok(())
+    }
+
+    fn spawn_text_to_play(&self, text_to_play: TextToPlay, speed: f32, start_part_duration: f32) -> Result<PlaybackHandle, MorseError> {
+        if text_to_play.main_chars.is_empty() {
+            return Err(MorseError::NoMorseText);
+        }
+        let (finished_sender, finished) = tokio::sync::oneshot::channel::<bool>();
+        let sink = self.sink.clone();
+        let stop_flag = self.stop_flag.clone();
+        let base_duration = text_to_play.base_duration;
+        let skipped_chars = text_to_play.skipped_chars;
+        let playback_handle = self.gen_playback_handle(&text_to_play, speed, start_part_duration, self.sink.clone(), self.stop_flag.clone(), finished);
+        let (_, char_offsets) = get_samples_and_char_offsets(&text_to_play, base_duration, speed, &self.get_element_table(), &self.synth)?;
+        let mut char_clock = CharClock { char_offsets, current_char: self.current_char.clone(), started_at: None };
+        let (mut synthesizer, mut right_synthesizer) = self.gen_binaural_synthesizers(text_to_play, speed)?;
+        let end_text_cut = playback_handle.end_text_cut.clone();
+        synthesizer.finish_flag = Some(self.finish_flag.clone());
+        synthesizer.end_text_cut = end_text_cut.clone();
+        if let Some(right_synthesizer) = right_synthesizer.as_mut() { // follows the cut of the left one
+            right_synthesizer.end_text_cut = end_text_cut.clone();
+        }
+        let underrun_callback = self.underrun_callback.clone();
+        let stop_fade = self.stop_fade;
+        let prerender = self.prerender;
+        let producer = self.producer.clone();
+        let last_play_report = self.last_play_report.clone();
+        let wpm = 60.0 / (50.0 * get_dot_duration(base_duration, speed));
+
+        if producer.is_running() { // stop the previous playing and wait for its producer to exit, the async plays have waited already
+            stop_flag.store(true, Ordering::SeqCst);
+            producer.wait();
+        }
+    
+        {
+            let unlocked_sink = lock_sink(&sink)?;
+            unlocked_sink.clear();
+            unlocked_sink.play();
+        }
+        stop_flag.store(false, Ordering::SeqCst);
+        self.finish_flag.store(false, Ordering::SeqCst);
+        producer.set_running(true);
+    
+        thread::spawn(move || {
+            let busy_guard = BusyGuard(producer); // cleared even if the thread panics, so the next play doesn't wait forever
+            let started_at = Instant::now();
+            let Ok(unlocked_sink) = lock_sink(&sink) else {
+                let _ = finished_sender.send(false);
+                return;
+            };
+            let (samples, completed) = play_audio(&mut synthesizer, right_synthesizer.as_mut(), &unlocked_sink, &stop_flag, underrun_callback.as_ref(), &mut char_clock,
+                PlayOptions { stop_fade, prerender });
+            drop(unlocked_sink);
+            char_clock.current_char.store(NO_CHAR, Ordering::SeqCst);
+            let completed = completed && end_text_cut.get().is_none();
+            *last_play_report.lock().unwrap_or_else(PoisonError::into_inner) = Some(PlayReport {
+                samples,
+                duration: started_at.elapsed(),
+                wpm,
+                element_count: synthesizer.get_played_element_count(),
+                completed,
+                skipped_chars,
+            });
+            drop(busy_guard);
+            let _ = finished_sender.send(completed);
+        });
+
+        Ok(playback_handle)
+    }
+
+    fn gen_playback_handle(&self, text_to_play: &TextToPlay, speed: f32, start_part_duration: f32, sink: Arc<Mutex<Sink>>, stop_flag: Arc<AtomicBool>,
+        finished: tokio::sync::oneshot::Receiver<bool>) -> PlaybackHandle { // with the callback times of text_to_play
+        let base_duration = text_to_play.base_duration;
+        let reveal_times: Vec<f32> = match self.reveal_callback {
+            Some(_) => get_word_end_times(text_to_play, base_duration, speed, &self.get_element_table())
+                .iter()
+                .map(|time| time + self.reveal_delay)
+                .collect(),
+            None => Vec::new(),
+        };
+        let lookahead_times: Vec<(f32, char)> = match self.lookahead_callback {
+            Some(_) => get_char_start_times(text_to_play, base_duration, speed, &self.get_element_table())
+                .iter()
+                .zip(&text_to_play.main_chars)
+                .map(|(time, ch)| ((time - self.lookahead).max(0.0), *ch))
+                .collect(),
+            None => Vec::new(),
+        };
+        let echo_times: Vec<(f32, char)> = match self.echo_writer {
+            Some(_) => {
+                let char_start_times = get_char_start_times(text_to_play, base_duration, speed, &self.get_element_table());
+                let last_char_start = char_start_times.last().copied().unwrap_or(0.0);
+                let mut echo_times: Vec<(f32, char)> = char_start_times.into_iter().zip(text_to_play.main_chars.iter().copied()).collect();
+                echo_times.extend(get_word_end_times(text_to_play, base_duration, speed, &self.get_element_table())
+                    .into_iter()
+                    .filter(|time| *time < last_char_start) // no space after the last word
+                    .map(|time| (time, ' ')));
+                echo_times.sort_by(|a, b| a.0.total_cmp(&b.0));
+                echo_times
+            }
+            None => Vec::new(),
+        };
+        PlaybackHandle {
+            element_times: get_element_start_times(text_to_play, base_duration, speed, &self.get_element_table()),
+            end_text_cut: Arc::new(OnceLock::new()),
+            sink,
+            stop_flag,
+            finished,
+            started_at: Instant::now(),
+            start_part_duration,
+            playing_started_callback: self.playing_started_callback.clone(),
+            playing_ended_callback: self.playing_ended_callback.clone(),
+            reveal_callback: self.reveal_callback.clone(),
+            reveal_times,
+            lookahead_callback: self.lookahead_callback.clone(),
+            lookahead_times,
+            echo_writer: self.echo_writer.clone(),
+            echo_times,
+        }
+    }
+    
+    pub async fn play_tone(&self, duration: Duration) -> Result<(), MorseError> { // steady reference tone, faded only at its edges, at most MAX_WAVE_DURATION long
+        if duration.is_zero() || duration.as_secs_f32() > MAX_WAVE_DURATION {
+            return Err(MorseError::InvalidDuration(duration));
+        }
+        let wave = get_wave(&self.synth, duration.as_secs_f32(), 1) * self.synth.output_gain;
+        self.play_samples(wave.to_vec()).await
+    }
+
+    pub async fn play_raw_elements(&self, elements: &[(bool, i32)]) -> Result<(), MorseError> { // (is_tone, units) at the base speed, without the code table
+        if let Some((_, units)) = elements.iter().find(|(is_tone, units)| *units < 0 || (*is_tone && *units == 0)) {
+            return Err(MorseError::InvalidUnits(*units));
+        }
+        let speed_to_use = get_dot_duration(self.get_base_duration(), self.speed);
+        let mut samples = Vec::<f32>::new();
+        for (is_tone, units) in elements {
+            if *is_tone {
+                samples.extend(get_wave(&self.synth, speed_to_use, *units).iter().map(|sample| sample * self.synth.output_gain));
+            } else {
+                samples.extend(get_silence(speed_to_use, *units));
+            }
+        }
+        self.play_samples(samples).await
+    }
+
+    async fn play_samples(&self, samples: Vec<f32>) -> Result<(), MorseError> { // replaces the current playing like the other plays
+        self.stop_producer().await;
+        {
+            let sink = lock_sink(&self.sink)?;
+            sink.clear();
+            sink.play();
+            sink.append(rodio::buffer::SamplesBuffer::new(1, SAMPLE_RATE, samples));
+        }
+        self.stop_flag.store(false, Ordering::SeqCst);
+        while lock_sink(&self.sink)?.len() != 0 {
+            if self.stop_flag.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            sleep(Duration::from_millis(5)).await;
+        }
+        Ok(())
+    }
+
+    pub fn stop(&self) -> Result<(), MorseError> {
+        self.stop_flag.store(true, Ordering::SeqCst);
+        lock_sink(&self.sink)?.clear();
+        Ok(())
+    }
+
+    pub fn finish_gracefully(&self) { // the character sounding ends, END_TEXT follows and playing ends, the audio already synthesized (up to about a second) plays first
+        self.finish_flag.store(true, Ordering::SeqCst);
+    }
+
+    pub fn set_prerender(&mut self, enabled: bool) { // synthesize everything before the audio starts, gapless on a loaded system but about 11.5 MB per minute and a later start
+        self.prerender = enabled;
+    }
+
+    pub fn set_stop_fade(&mut self, secs: f32) { // stopping play or spawn_play fades the output out over secs instead of cutting it
+        self.stop_fade = secs.max(0.0);
+    }
+
+    pub fn current_char_index(&self) -> Option<usize> { // main text character sounding now during play or spawn_play, counted on over repeats, the last one stays during the gap after it
+        Some(self.current_char.load(Ordering::SeqCst)).filter(|index| *index != NO_CHAR)
+    }
+
+    pub fn last_play_report(&self) -> Option<PlayReport> { // set when the audio thread of the last play exits
+        *self.last_play_report.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock_actions_length(&self) -> MutexGuard<'_, HashMap<char, (i32, i32)>> { // inserts can't panic half way, so a poisoned map is still valid
+        self.actions_length.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn get_element_table(&self) -> HashMap<char, (i32, i32)> { // with the fixed pauses, for timing and playing
+        self.fixed_pauses.add_to(self.lock_actions_length().clone())
+    }
+
+    pub fn connect_main_text_started_callback<F>(&mut self, callback: F)
+    where
+        F: Fn() + 'static,
+    {
+        self.playing_started_callback = Some(Arc::new(callback));
+    }
+
+    pub fn connect_playing_ended_callback<F>(&mut self, callback: F)
+    where
+        F: Fn() + 'static,
+    {
+        self.playing_ended_callback = Some(Arc::new(callback));
+    }
+
+    pub fn connect_reveal_callback<F>(&mut self, callback: F) // word index, fires the reveal delay after every word of the main text
+    where
+        F: Fn(usize) + 'static,
+    {
+        self.reveal_callback = Some(Arc::new(callback));
+    }
+
+    pub fn set_echo_writer(&mut self, writer: Box<dyn Write + Send>) { // every main text character is written as its first tone sounds, a space between words, flushed every time
+        self.echo_writer = Some(Arc::new(Mutex::new(writer)));
+    }
+
+    pub fn set_reveal_delay(&mut self, secs: f32) {
+        self.reveal_delay = secs.max(0.0);
+    }
+
+    pub fn connect_lookahead_callback<F>(&mut self, callback: F) // character, fires the lookahead before the first tone of every character of the main text
+    where
+        F: Fn(char) + 'static,
+    {
+        self.lookahead_callback = Some(Arc::new(callback));
+    }
+
+    pub fn set_lookahead(&mut self, secs: f32) { // zero or less fires right at the start of the character
+        self.lookahead = secs.max(0.0);
+    }
+
+    pub fn connect_underrun_callback<F>(&mut self, callback: F) // sink ran dry while audio was still being generated
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.underrun_callback = Some(Arc::new(callback));
+    }
+
+    pub fn set_delay(&mut self, delay: i32) { // character gap in units, the word gap follows it
+        let mut actions_length = self.lock_actions_length();
+        actions_length.insert('$', (1, delay));
+        actions_length.insert('/', (1, (delay as f64 * 2.33) as i32));
+    }
+
+    pub fn set_element_units(&mut self, element: char, on_off: i32, units: i32) -> Result<(), MorseError> { // on_off: 0 - tone, 1 - pause
+        if !element.is_ascii_punctuation() || RESERVED_ELEMENTS.contains(&element) {
+            return Err(MorseError::InvalidElement(element));
+        }
+        if on_off != 0 && on_off != 1 {
+            return Err(MorseError::InvalidAction(on_off));
+        }
+        if units < 0 || (on_off == 0 && units == 0) { // a tone needs at least one sample
+            return Err(MorseError::InvalidUnits(units));
+        }
+        self.lock_actions_length().insert(element, (on_off, units));
+        Ok(())
+    }
+
+    pub fn set_char_code(&mut self, ch: char, code: &str) -> Result<(), MorseError> { // pattern of tone elements, custom ones from set_element_units too, e.g. "._" with a 5 unit '_'
+        if ch.is_whitespace() || code.is_empty() {
+            return Err(MorseError::UnsupportedChar(ch));
+        }
+        let actions_length = self.lock_actions_length();
+        if let Some(element) = code.chars().find(|element| !matches!(actions_length.get(element), Some((0, _)))) {
+            return Err(MorseError::InvalidElement(element));
+        }
+        drop(actions_length);
+        self.generator.code_table.insert(ch, code.to_string());
+        Ok(())
+    }
+
+    pub fn get_actions_length(&self) -> HashMap<char, (i32, i32)> { // tones, pauses and speed changes, without the fixed pauses
+        self.lock_actions_length().clone()
+    }
+
+    pub fn set_modification_length(&mut self, length: i32) {
+        self.modification_len = length;
+    }
+
+    pub fn set_frequency(&mut self, frequency: i32) {
+        self.synth.frequency = frequency;
+    }
+
+    pub fn set_random_frequency(&mut self, range: RangeInclusive<i32>, seed: Option<u64>) { // every play sounds at a frequency picked from range, renders keep the set frequency, an empty range turns it off
+        self.random_frequency = (!range.is_empty()).then(|| (range, Mutex::new(gen_rng(seed))));
+    }
+
+    pub fn clear_random_frequency(&mut self) { // plays sound at the set frequency again
+        self.random_frequency = None;
+    }
+
+    pub fn set_binaural(&mut self, beat_hz: f32) -> Result<(), MorseError> { // playing is stereo with the right channel beat_hz above the left one, heard as a beat only with headphones, renders stay mono, 0.0 disables it
+        if !(0.0..(SAMPLE_RATE / 2) as f32).contains(&beat_hz) { // NaN too
+            return Err(MorseError::InvalidFrequency(beat_hz));
+        }
+        self.synth.binaural_beat = beat_hz;
+        Ok(())
+    }
+
+    pub fn set_pitch_shift(&mut self, ratio: f32) { // every tone sounds at the frequency * ratio, kept below the Nyquist frequency, unlike set_playback_rate the timing doesn't change
+        self.synth.pitch_shift = ratio.max(0.0);
+    }
+    
+    pub fn set_wave_type(&mut self, wave_type: WaveType) {
+        self.synth.wave_type = wave_type;
+    }
+
+    pub fn set_harmonics(&mut self, wave_type: WaveType, count: Option<u32>) { // terms of the Fourier series, None for the most that don't alias at the current frequency, sine has none
+        match wave_type {
+            WaveType::Square => self.synth.square_harmonics = count,
+            WaveType::Triangle => self.synth.triangle_harmonics = count,
+            WaveType::Sawtooth => self.synth.sawtooth_harmonics = count,
+            WaveType::Sine => {}
+        }
+    }
+
+    pub fn set_marker_volume(&mut self, factor: f32) -> Result<(), MorseError> { // start and end parts amplitude relative to the main text
+        if !factor.is_finite() || factor < 0.0 {
+            return Err(MorseError::InvalidGain(factor));
+        }
+        self.synth.marker_volume = factor;
+        Ok(())
+    }
+
+    pub fn set_loudness_match(&mut self, enabled: bool) { // same perceived loudness for all wave types
+        self.synth.loudness_match = enabled;
+    }
+
+    pub fn set_lowpass(&mut self, cutoff_hz: Option<f32>) -> Result<(), MorseError> { // None disables the filter
+        if let Some(cutoff_hz) = cutoff_hz {
+            if !(cutoff_hz > 0.0 && cutoff_hz < (SAMPLE_RATE / 2) as f32) { // NaN too
+                return Err(MorseError::InvalidFrequency(cutoff_hz));
+            }
+        }
+        self.synth.lowpass = cutoff_hz;
+        Ok(())
+    }
+
+    pub fn set_noise(&mut self, snr_db: Option<f32>, noise_type: NoiseType) -> Result<(), MorseError> { // background noise against a full scale sine tone, None disables it
+        if let Some(snr_db) = snr_db.filter(|snr_db| !snr_db.is_finite()) {
+            return Err(MorseError::InvalidDecibels(snr_db));
+        }
+        self.synth.noise = snr_db.map(|snr_db| (snr_db, noise_type));
+        Ok(())
+    }
+
+    pub fn set_fade_mode(&mut self, fade_mode: FadeMode) -> Result<(), MorseError> { // FractionOfDit keeps the tone shape the same at every speed
+        if let FadeMode::FractionOfDit(fraction) = fade_mode {
+            if !(0.0..=0.5).contains(&fraction) { // NaN too
+                return Err(MorseError::InvalidFadeFraction(fraction));
+            }
+        }
+        self.synth.fade_mode = fade_mode;
+        Ok(())
+    }
+
+    pub fn set_phase_continuous(&mut self, enabled: bool) { // every tone starts at the phase the previous one ended, cleaner sound (mostly for Sine) but no cached waves
+        self.synth.phase_continuous = enabled;
+    }
+
+    pub fn set_envelope_fn(&mut self, envelope: EnvelopeFn) { // shape of every tone instead of the Hann fades, joined tones get one envelope over their whole length
+        self.synth.envelope = Some(envelope);
+    }
+
+    pub fn clear_envelope_fn(&mut self) { // back to the Hann fades
+        self.synth.envelope = None;
+    }
+
+    pub fn set_zero_crossing_start(&mut self, enabled: bool) { // phase continuous tones start at the nearest zero crossing of the wave instead of the phase the last one ended, see TONE EDGES
+        self.synth.zero_crossing_start = enabled;
+    }
+
+    pub fn set_smooth_joins(&mut self, enabled: bool) { // tones without a pause between them sound as one tone
+        self.synth.smooth_joins = enabled;
+    }
+
+    pub fn set_inverted(&mut self, enabled: bool) { // tone during pauses and silence during tones, fixed pauses stay silent
+        self.synth.inverted = enabled;
+    }
+
+    pub fn set_sine_lookup(&mut self, enabled: bool) { // table instead of sin() for slow devices, differs from sin() by less than 5e-6
+        self.synth.sine_lookup = enabled;
+    }
+
+    pub fn set_fist(&mut self, amount: f32, seed: Option<u64>) { // random loudness change of every tone, up to +-amount
+        self.synth.fist_amount = amount.max(0.0);
+        self.synth.fist_seed = seed;
+    }
+
+    pub fn set_volume(&mut self, volume: f32) -> Result<(), MorseError> { // monitor loudness, doesn't change rendered files
+        lock_sink(&self.sink)?.set_volume(volume);
+        Ok(())
+    }
+
+    pub fn set_volume_db(&mut self, db: f32) -> Result<(), MorseError> { // 0 dB is set_volume(1.0)
+        if !db.is_finite() {
+            return Err(MorseError::InvalidDecibels(db));
+        }
+        let volume = if db <= MIN_VOLUME_DB { 0.0 } else { 10.0_f32.powf(db / 20.0) };
+        self.set_volume(volume)
+    }
+
+    pub fn volume_db(&self) -> Result<f32, MorseError> { // -60 for silence
+        let volume = lock_sink(&self.sink)?.volume();
+        Ok(if volume > 0.0 { (20.0 * volume.log10()).max(MIN_VOLUME_DB) } else { MIN_VOLUME_DB })
+    }
+
+    pub fn set_playback_rate(&self, rate: f32) -> Result<(), MorseError> { // resamples the output, so the pitch changes with the rate, set_speed changes the timing only
+        lock_sink(&self.sink)?.set_speed(rate);
+        Ok(())
+    }
+
+    pub fn set_output_gain(&mut self, gain: f32) -> Result<(), MorseError> { // loudness of generated samples, for playing and rendering alike
+        if !gain.is_finite() || gain < 0.0 {
+            return Err(MorseError::InvalidGain(gain));
+        }
+        self.synth.output_gain = gain;
+        Ok(())
+    }
+
+    pub fn set_element_gains(&mut self, dot_gain: f32, dash_gain: f32) { // custom tones from set_element_units keep full gain, joined tones get the gain of the first one
+        self.synth.dot_gain = dot_gain.max(0.0);
+        self.synth.dash_gain = dash_gain.max(0.0);
+    }
+
+    pub fn set_render_char_gap(&mut self, enabled: bool) { // trailing character gap in render_chars_to_dir files
+        self.render_char_gap = enabled;
+    }
+
+    pub fn set_word_speeds(&mut self, speeds: HashMap<usize, f32>) -> Result<(), MorseError> { // word index -> speed, overrides the speed modification inside the word too
+        if let Some(speed) = speeds.values().find(|speed| !speed.is_finite() || **speed <= 0.0) {
+            return Err(MorseError::InvalidSpeed(*speed));
+        }
+        self.generator.word_speeds = speeds;
+        Ok(())
+    }
+
+    pub fn set_unknown_char_policy(&mut self, policy: UnknownPolicy) {
+        self.generator.unknown_policy = policy;
+    }
+
+    pub fn set_cut_numbers(&mut self, enabled: bool) {
+        self.generator.cut_numbers = enabled;
+    }
+
+    pub fn set_cut_number_table(&mut self, table: HashMap<char, char>) { // digit -> letter sent instead
+        self.generator.cut_number_table = table;
+    }
+
+    pub fn set_wav_bit_depth(&mut self, bit_depth: BitDepth) {
+        self.wav_bit_depth = bit_depth;
+    }
+
+    pub fn set_repeat(&mut self, times: u32, gap_secs: f32) { // times is the total count of plays
+        self.repeat_times = times.max(1);
+        self.fixed_pauses.repeat_gap = (gap_secs.max(0.0) * 1000.0).round() as i32;
+    }
+
+    pub fn set_emphasis_chars(&mut self, chars: &[char], speed_factor: f32) -> Result<(), MorseError> { // chars play at speed * speed_factor, with a modification at the speed of their position * speed_factor
+        if !speed_factor.is_finite() || speed_factor <= 0.0 {
+            return Err(MorseError::InvalidSpeed(speed_factor));
+        }
+        self.generator.emphasis_chars = chars.to_vec();
+        self.generator.emphasis_factor = speed_factor;
+        Ok(())
+    }
+
+    pub fn set_pad(&mut self, leading_secs: f32, trailing_secs: f32) { // silence before and after everything played or rendered, in the durations and offsets too
+        self.fixed_pauses.leading_pad = (leading_secs.max(0.0) * 1000.0).round() as i32;
+        self.fixed_pauses.trailing_pad = (trailing_secs.max(0.0) * 1000.0).round() as i32;
+    }
+
+    pub fn set_pre_text_gap(&mut self, secs: f32) { // silence after the start part, counted as a part of it
+        self.fixed_pauses.pre_text_gap = (secs.max(0.0) * 1000.0).round() as i32;
+    }
+
+    pub fn set_announcement_speed(&mut self, speed: Option<f32>) { // speed of the competitions speed number, None plays it at the start speed
+        self.announcement_speed = speed.filter(|speed| *speed > 0.0);
+    }
+
+    pub fn set_shuffle(&mut self, enabled: bool, seed: Option<u64>) { // every play sends the words in a new order, characters inside words stay in place, renders keep the set order
+        self.shuffle_rng = enabled.then(|| Mutex::new(gen_rng(seed)));
+    }
+
+    pub fn set_cq_template(&mut self, template: &str) { // text of call_cq, every {call} becomes the callsign
+        self.cq_template = template.to_string();
+    }
+
+    pub fn set_periodic_id(&mut self, callsign: &str, interval_secs: f32) { // callsign at the word gap nearest to every interval_secs of the main text, an empty callsign disables it
+        let callsign: Vec<char> = callsign.chars().flat_map(|ch| ch.to_uppercase()).collect();
+        self.periodic_id = (!callsign.iter().all(|ch| ch.is_whitespace()) && interval_secs > 0.0).then_some((callsign, interval_secs));
+    }
+
+    pub fn set_preamble_text(&mut self, text: &str) { // played instead of the start additions at the announcement speed when they're enabled, an empty text brings them back
+        let preamble: Vec<char> = text.trim().chars().flat_map(|ch| ch.to_uppercase()).collect();
+        self.preamble = (!preamble.is_empty()).then_some(preamble);
+    }
+
+    pub fn set_countdown(&mut self, from: u8) { // digits from..1 before the start part at the announcement speed, 0 disables it
+        self.countdown = from;
+    }
+
+    pub fn set_announcement_gap(&mut self, units: Option<i32>) { // pause between the digits of the speed number, set_delay doesn't change it, None brings back the character gap
+        self.announcement_gap = units.is_some();
+        if let Some(units) = units {
+            self.lock_actions_length().insert('!', (1, units.max(0)));
+        }
+    }
+
+    pub fn set_group_gap(&mut self, units: Option<i32>) { // pause between words of a text made of equal-length groups, set_delay doesn't change it, None brings back the word gap
+        self.generator.group_gap = units.is_some();
+        if let Some(units) = units {
+            self.lock_actions_length().insert('%', (1, units.max(0)));
+        }
+    }
+
+    pub fn set_merge_chars(&mut self, enabled: bool, element_gaps: bool) { // characters of a word without pauses between them, element_gaps false also drops the ones inside characters
+        self.generator.merge_chars = enabled;
+        self.generator.element_gaps = element_gaps;
+        self.lock_actions_length().insert('+', (1, if element_gaps { 1 } else { 0 }));
+    }
+
+    pub fn set_word_echo(&mut self, enabled: bool, gap_secs: f32) { // every word is sent twice
+        self.generator.word_echo = enabled;
+        self.fixed_pauses.echo_gap = (gap_secs.max(0.0) * 1000.0).round() as i32;
+    }
+
+    pub fn set_repeat_markers(&mut self, enabled: bool) { // start and end parts around every repeat instead of the whole sequence
+        self.repeat_markers = enabled;
+    }
+
+    pub fn set_text_additions(&mut self, text_additions: TextAdditions) {
+        self.text_additions = text_additions;
+        self.start_marker = text_additions != TextAdditions::None;
+        self.end_marker = text_additions != TextAdditions::None;
+    }
+
+    pub fn set_start_marker(&mut self, enabled: bool) {
+        self.start_marker = enabled;
+    }
+
+    pub fn set_end_marker(&mut self, enabled: bool) {
+        self.end_marker = enabled;
+    }
+}
+
+/*
+
+    PLAYBACK HANDLE:
+        spawn_play starts the audio right away and returns a PlaybackHandle.
+        Awaiting the handle waits for the end of playing and runs the main text started,
+        reveal, lookahead and playing ended callbacks, so they only fire while somebody awaits it.
+        Reveals later than the end of the audio are still waited for, unless it was stopped.
+        PlaybackHandle::stop is the same as AudioPlayer::stop, the handle still
+        completes (and fires the ended callback) after stopping. With a stop fade
+        set, stop returns after the fade. After AudioPlayer::finish_gracefully the
+        callbacks of the cut text don't fire and the playing counts as stopped.
+        Callbacks are timed from the spawn, with prerender on they come early
+        by the time the rendering took.
+        Calling play, spawn_play or interrupt_with while already playing stops the current playing,
+        waits until its audio thread exits and then starts the new one.
+
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MorseEvent {
+    pub start: f32, // seconds since the start of the playing
+    pub duration: f32, // seconds
+    pub tone: bool, // false for pauses
+    pub char_index: Option<usize>, // sent main text character the event is a part of, None for gaps and the start and end parts
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PlayReport {
+    pub samples: usize, // sent to the output
+    pub duration: Duration, // measured from the start of the audio thread to its end
+    pub wpm: f32, // PARIS words per minute at the start speed
+    pub element_count: usize, // tones and pauses played, start and end parts included
+    pub completed: bool, // false if stopped or finished gracefully
+    pub skipped_chars: usize, // characters without Morse code, counted with UnknownPolicy::Skip only
+}
+
+pub struct PlaybackHandle {
+    sink: Arc<Mutex<Sink>>,
+    stop_flag: Arc<AtomicBool>,
+    finished: tokio::sync::oneshot::Receiver<bool>, // true if the audio reached its end
+    started_at: Instant,
+    start_part_duration: f32,
+    playing_started_callback: Option<PlayingStartedCallback>,
+    playing_ended_callback: Option<PlayingEndedCallback>,
+    reveal_callback: Option<RevealCallback>,
+    reveal_times: Vec<f32>, // since the start, word order
+    lookahead_callback: Option<LookaheadCallback>,
+    lookahead_times: Vec<(f32, char)>, // since the start, character order
+    echo_writer: Option<EchoWriter>,
+    echo_times: Vec<(f32, char)>, // since the start, characters and spaces in order
+    element_times: Vec<f32>, // since the start, of every element
+    end_text_cut: Arc<OnceLock<usize>>, // element where finish_gracefully cut the text
+}
+
+impl PlaybackHandle {
+    pub fn stop(&self) -> Result<(), MorseError> {
+        self.stop_flag.store(true, Ordering::SeqCst);
+        lock_sink(&self.sink)?.clear();
+        Ok(())
+    }
+
+    async fn wait(self) {
+        let mut finished = self.finished;
+        let mut completed: Option<bool> = None;
+        let mut events: Vec<(f32, Box<dyn Fn()>)> = Vec::new();
+        if let Some(callback) = self.playing_started_callback {
+            events.push((self.start_part_duration, Box::new(move || callback())));
+        }
+        if let Some(callback) = self.reveal_callback {
+            for (word, time) in self.reveal_times.into_iter().enumerate() {
+                let callback = callback.clone();
+                events.push((time, Box::new(move || callback(word))));
+            }
+        }
+        if let Some(callback) = self.lookahead_callback {
+            for (time, ch) in self.lookahead_times {
+                let callback = callback.clone();
+                events.push((time, Box::new(move || callback(ch))));
+            }
+        }
+        if let Some(writer) = self.echo_writer {
+            for (time, ch) in self.echo_times {
+                let writer = writer.clone();
+                events.push((time, Box::new(move || {
+                    let mut writer = writer.lock().unwrap_or_else(PoisonError::into_inner);
+                    let _ = write!(writer, "{}", ch).and_then(|_| writer.flush()); // a failing writer doesn't stop the playing
+                })));
+            }
+        }
+        events.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let element_times = self.element_times;
+        let end_text_cut = self.end_text_cut;
+        let is_cut = |time: f32| end_text_cut.get().is_some_and(|cut| time >= element_times.get(*cut).copied().unwrap_or(f32::INFINITY));
+        for (time, callback) in events {
+            let delay = Duration::from_secs_f32(time).saturating_sub(self.started_at.elapsed());
+            if completed.is_none() {
+                tokio::select! {
+                    result = &mut finished => completed = Some(result.unwrap_or(false)),
+                    _ = sleep(delay) => {
+                        if !is_cut(time) { // not sent after finish_gracefully
+                            callback();
+                        }
+                        continue;
+                    }
+                }
+            }
+            if completed != Some(true) { // stopped, skip the rest
+                break;
+            }
+            sleep(delay).await; // reveals after the last word can come after the audio ends
+            callback();
+        }
+        if completed.is_none() {
+            let _ = finished.await;
+        }
+        if let Some(callback) = self.playing_ended_callback {
+            callback();
+        }
+    }
+}
+
+impl IntoFuture for PlaybackHandle {
+    type Output = ();
+    type IntoFuture = Pin<Box<dyn Future<Output = ()>>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(self.wait())
+    }
+}
+
+/*
+
+    PLAYER POOL:
+        A PlayerPool owns one audio output (or a headless one) and one worker thread. Players
+        from new_player have their sinks in the pool's mixer, so their own play and spawn_play
+        work as usual, without an output stream of their own. PlayerPool::play takes the text
+        and settings of any player when it's called and gives the job to the worker, which goes
+        round the playing jobs every 5 ms and tops up their sinks a block at a time, the way
+        play_audio does for a single player. Every job has its own sink, so jobs of the same or
+        different players play at the same time and are mixed, the handle stops only its own job.
+        The handle runs the callbacks of the player as spawn_play does, on the task awaiting it.
+        Pool jobs play in mono without the stop fade and prerendering, and they don't set
+        current_char_index or last_play_report. The worker ends when the pool is dropped and
+        the last job has finished.
+
+*/
+
+struct PoolJob {
+    synthesizer: Synthesizer,
+    sink: Arc<Mutex<Sink>>,
+    stop_flag: Arc<AtomicBool>,
+    finished_sender: tokio::sync::oneshot::Sender<bool>,
+}
+
+pub struct PlayerPool {
+    _stream: Option<OutputStream>,
+    _keep_alive: Sink, // its silence keeps the mixer running while nothing plays
+    mixer: Arc<rodio::dynamic_mixer::DynamicMixerController<f32>>,
+    jobs: std::sync::mpsc::Sender<PoolJob>,
+}
+
+impl PlayerPool {
+    pub fn new() -> PlayerPool { // panics without an output device, try_new returns the error
+        PlayerPool::try_new().expect("default audio output can't be opened")
+    }
+
+    pub fn try_new() -> Result<PlayerPool, MorseError> {
+        let (stream, stream_handle) = OutputStream::try_default().map_err(|_| MorseError::NoOutputDevice)?;
+        let (mixer, output) = rodio::dynamic_mixer::mixer::<f32>(1, SAMPLE_RATE);
+        let pool = PlayerPool::from_mixer(mixer, Some(stream));
+        stream_handle.play_raw(output).map_err(|_| MorseError::NoOutputDevice)?;
+        Ok(pool)
+    }
+
+    pub fn new_headless() -> PlayerPool { // without an output device, like AudioPlayer::new_headless
+        let (mixer, output) = rodio::dynamic_mixer::mixer::<f32>(1, SAMPLE_RATE);
+        let pool = PlayerPool::from_mixer(mixer, None);
+        thread::spawn(move || drain_in_real_time(output));
+        pool
+    }
+
+    fn from_mixer(mixer: Arc<rodio::dynamic_mixer::DynamicMixerController<f32>>, stream: Option<OutputStream>) -> PlayerPool {
+        let (keep_alive, keep_alive_output) = Sink::new_idle();
+        mixer.add(keep_alive_output);
+        let (jobs, jobs_receiver) = std::sync::mpsc::channel::<PoolJob>();
+        thread::spawn(move || run_pool(jobs_receiver));
+        PlayerPool { _stream: stream, _keep_alive: keep_alive, mixer, jobs }
+    }
+
+    pub fn new_player(&self) -> AudioPlayer { // plays into the pool's output
+        AudioPlayer::from_sink(self.new_sink(), None, None)
+    }
+
+    pub fn play(&self, player: &AudioPlayer) -> Result<PlaybackHandle, MorseError> { // the text of the player with its settings, on the worker thread
+        player.check_unknown_chars(&player.text)?;
+        let text_to_play = player.gen_text_to_play();
+        if text_to_play.main_chars.is_empty() {
+            return Err(MorseError::NoMorseText);
+        }
+        let speed = player.get_start_speed();
+        let sink = self.new_sink();
+        sink.set_volume(lock_sink(&player.sink)?.volume());
+        let sink = Arc::new(Mutex::new(sink));
+        let stop_flag = Arc::new(AtomicBool::new(false));
+        let (finished_sender, finished) = tokio::sync::oneshot::channel::<bool>();
+        let start_part_duration = player.get_leading_pad() + player.get_start_part_duration()?;
+        let playback_handle = player.gen_playback_handle(&text_to_play, speed, start_part_duration, sink.clone(), stop_flag.clone(), finished);
+        let synthesizer = player.gen_synthesizer_with(text_to_play, speed, player.gen_play_synth())?;
+        let _ = self.jobs.send(PoolJob { synthesizer, sink, stop_flag, finished_sender }); // a dead worker drops the job, the handle ends as stopped
+        Ok(playback_handle)
+    }
+
+    fn new_sink(&self) -> Sink {
+        let (sink, output) = Sink::new_idle();
+        self.mixer.add(output);
+        sink
+    }
+}
+
+impl Default for PlayerPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn run_pool(jobs: std::sync::mpsc::Receiver<PoolJob>) {
+    let mut playing = Vec::<PoolJob>::new();
+    loop {
+        if playing.is_empty() {
+            match jobs.recv() {
+                Ok(job) => playing.push(job),
+                Err(_) => return, // the pool is dropped
+            }
+        }
+        playing.extend(jobs.try_iter());
+        let mut i = 0;
+        while i < playing.len() {
+            match step_pool_job(&mut playing[i]) {
+                Some(completed) => {
+                    let job = playing.swap_remove(i);
+                    let _ = job.finished_sender.send(completed);
+                }
+                None => i += 1,
+            }
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+fn step_pool_job(job: &mut PoolJob) -> Option<bool> { // Some(completed) when the job is over
+    if job.stop_flag.load(Ordering::SeqCst) { // the handle has cleared the sink
+        return Some(false);
+    }
+    let Ok(sink) = lock_sink(&job.sink) else {
+        return Some(false);
+    };
+    while sink.len() <= SINK_BUFFER_SIZE as usize {
+        match job.synthesizer.next_block() {
+            Some(sound_signal) => sink.append(rodio::buffer::SamplesBuffer::new(1, SAMPLE_RATE, sound_signal)),
+            None => break,
+        }
+    }
+    (job.synthesizer.is_finished() && sink.empty()).then_some(true)
+}
+
+/*
+
+    PLAYER SETTINGS:
+        Text settings of an AudioPlayer (speeds, text type, element lengths and the code table)
+        without its audio output, for analysing texts. Get them from a player with
+        get_settings or start from the defaults of a new player.
+
+*/
+
+#[derive(Clone)]
+pub struct PlayerSettings {
+    speed: f32,
+    speed_modification_type: SpeedModificationType,
+    min_speed: f32,
+    max_speed: f32,
+    modification_len: i32,
+    actions_length: HashMap<char, (i32, i32)>,
+    generator: GeneratorSettings,
+    text_type: TextType,
+    letters_duration: f32,
+    digits_duration: f32,
+    mixed_duration: f32,
+}
+
+impl PlayerSettings {
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    pub fn get_text_duration(&self, text: &str) -> Result<f32, MorseError> { // as AudioPlayer::get_text_duration would give with this text set
+        let text: Vec<char> = text.chars().flat_map(|ch| ch.to_uppercase()).collect();
+        let (speed_pattern, text_preview) = self.gen_main_text(&text);
+        let (text_time, _) = get_time_and_timings(&text_preview, self.get_base_duration(&text), self.get_start_speed(), &speed_pattern, &self.actions_length)?;
+        Ok(text_time)
+    }
+
+    fn get_base_duration(&self, text: &[char]) -> f32 {
+        get_base_duration_for(get_text_type_for(self.text_type, text), self.letters_duration, self.digits_duration, self.mixed_duration)
+    }
+
+    fn get_start_speed(&self) -> f32 {
+        get_start_speed_for(self.speed_modification_type, self.speed, self.min_speed, self.max_speed)
+    }
+
+    fn gen_main_text(&self, text: &[char]) -> (Vec<f32>, Vec<char>) {
+        gen_audio_prev_vec(text, self.speed, self.min_speed, self.max_speed, self.speed_modification_type, self.modification_len, &self.generator)
+    }
+}
+
+impl Default for PlayerSettings {
+    fn default() -> Self {
+        PlayerSettings {
+            speed: 100.0,
+            speed_modification_type: SpeedModificationType::None,
+            min_speed: 100.0,
+            max_speed: 110.0,
+            modification_len: 10,
+            actions_length: FixedPauses::default().add_to(get_default_actions_length()),
+            generator: GeneratorSettings::default(),
+            text_type: TextType::Letters,
+            letters_duration: LETTERS_DURATION,
+            digits_duration: DIGITS_DURATION,
+            mixed_duration: MIXED_DURATION,
+        }
+    }
+}
+
+pub fn relative_difficulty(a: &str, b: &str, settings: &PlayerSettings) -> f32 { // above 1.0 if a is harder than b
+    let difficulty_a = get_difficulty(a, settings);
+    let difficulty_b = get_difficulty(b, settings);
+    if difficulty_b == 0.0 {
+        return if difficulty_a == 0.0 { 1.0 } else { f32::INFINITY };
+    }
+    difficulty_a / difficulty_b
+}
+
+fn get_difficulty(text: &str, settings: &PlayerSettings) -> f32 { // element density * average word length * (1 + average character rarity)
+    let chars: Vec<char> = text.chars().flat_map(|ch| ch.to_uppercase()).filter(|ch| ch.is_whitespace() || settings.generator.code_table.contains_key(ch)).collect();
+    let (_, elements) = settings.gen_main_text(&chars);
+    let dits = count_dits(&elements, &settings.actions_length);
+    if dits == 0 {
+        return 0.0;
+    }
+    let element_density = count_elements(&elements, &settings.actions_length) as f32 / dits as f32;
+    let words: Vec<&[char]> = chars.split(|ch| ch.is_whitespace()).filter(|word| !word.is_empty()).collect();
+    let chars_count: usize = words.iter().map(|word| word.len()).sum();
+    let average_word_length = chars_count as f32 / words.len() as f32;
+    let average_rarity = words.iter().flat_map(|word| word.iter()).map(|ch| get_char_rarity(*ch)).sum::<f32>() / chars_count as f32;
+    element_density * average_word_length * (1.0 + average_rarity)
+}
+
+fn get_char_rarity(ch: char) -> f32 { // 0.0 for the most frequent English letter, 1.0 for everything except letters
+    match LETTERS_BY_FREQUENCY.iter().position(|letter| *letter == ch) {
+        Some(rank) => rank as f32 / LETTERS_BY_FREQUENCY.len() as f32,
+        None => 1.0,
+    }
+}
+
+fn get_default_actions_length() -> HashMap<char, (i32, i32)> {
+    let mut m = HashMap::new();
+    m.insert('.', (0, 1));
+    m.insert('-', (0, 3));
+    m.insert('*', (1, 1));
+    m.insert('$', (1, 3));
+    m.insert('/', (1, 7));
+    m.insert('%', (1, 7));
+    m.insert('!', (1, 3));
+    m.insert('+', (1, 1));
+    m.insert('|', (2, 0));
+    m
+}
+
+#[derive(Clone, Copy, Default)]
+struct FixedPauses { // milliseconds, they don't scale with the speed, so they're settings of their own and not in the element table
+    repeat_gap: i32, // ~
+    echo_gap: i32, // #
+    pre_text_gap: i32, // ^
+    leading_pad: i32, // <
+    trailing_pad: i32, // >
+}
+
+impl FixedPauses {
+    fn add_to(&self, mut actions_length: HashMap<char, (i32, i32)>) -> HashMap<char, (i32, i32)> { // as elements with action 3
+        for (element, length) in [('~', self.repeat_gap), ('#', self.echo_gap), ('^', self.pre_text_gap), ('<', self.leading_pad), ('>', self.trailing_pad)] {
+            actions_length.insert(element, (3, length));
+        }
+        actions_length
+    }
+}
+
+fn count_elements(elements: &[char], actions_length: &HashMap<char, (i32, i32)>) -> usize { // tones and pauses
+    elements.iter()
+        .filter(|element| matches!(actions_length.get(element), Some((0, _)) | Some((1, _))))
+        .count()
+}
+
+fn count_dits(elements: &[char], actions_length: &HashMap<char, (i32, i32)>) -> usize {
+    elements.iter()
+        .filter_map(|element| actions_length.get(element))
+        .filter(|(action, _)| *action == 0 || *action == 1)
+        .map(|(_, units)| *units as usize)
+        .sum()
+}
+
+pub fn morse_to_text(morse: &str) -> String { // letters separated by spaces, words by '/', '?' for unknown patterns
+    let letters: HashMap<&str, char> = MORSE_CODE.iter().map(|(letter, code)| (*code, *letter)).collect();
+    let mut text = String::new();
+    for pattern in morse.split_whitespace() {
+        if pattern == "/" {
+            text.push(' ');
+        } else {
+            text.push(*letters.get(pattern).unwrap_or(&'?'));
+        }
+    }
+    text
+}
+
+pub async fn play_message(text: &str, wpm: f32) -> Result<(), MorseError> { // the text once at wpm PARIS words per minute, without start and end parts
+    let mut player = AudioPlayer::new_with_text(text)?;
+    player.set_text_type(TextType::Auto);
+    player.set_text_additions(TextAdditions::None);
+    player.set_speed(get_speed_for_wpm(player.get_base_duration(), wpm)?);
+    player.play().await
+}
+
+pub fn samples_checksum(samples: &[f32]) -> u64 { // see DETERMINISTIC RENDERING
+    let mut hash: u64 = 0xCBF29CE484222325; // 64-bit FNV-1a
+    for sample in samples {
+        let quantized = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+        for byte in quantized.to_le_bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001B3);
+        }
+    }
+    hash
+}
+
+/*
+
+    DETERMINISTIC RENDERING:
+        render_samples_deterministic gives the same samples as render_samples, except that
+        the random sources without a seed get a fixed one: the fist (set_fist without a seed)
+        and the background noise. So the same settings and text give the same samples on
+        every call. Shuffling never changes renders. Element lengths are cut to whole samples
+        the same way everywhere, so sample counts and offsets don't depend on the system.
+        Samples are bit for bit equal on the same platform and build. Elsewhere the last
+        bits can differ, as sin and powf come from the system math library.
+        samples_checksum hashes the samples quantized to 16 bits (64-bit FNV-1a over the
+        little endian bytes), so it stays the same across platforms unless a sample lands
+        right on a rounding edge. It doesn't depend on the Rust version, but changes to the
+        synthesis in this crate change the checksums of golden files.
+
+*/
+
+pub struct MorseSource { // rodio source generating the audio of a player lazily
+    synthesizer: Synthesizer,
+    block: Vec<f32>,
+    block_position: usize,
+    total_duration: Duration,
+}
+
+impl MorseSource {
+    pub fn new(player: &AudioPlayer) -> Result<MorseSource, MorseError> {
+        player.check_unknown_chars(&player.text)?;
+        player.check_morse_text()?;
+        let text_to_play = player.gen_text_to_play();
+        let total_duration = Duration::from_secs_f32(player.get_duration_of(&text_to_play)?);
+        Ok(MorseSource {
+            synthesizer: player.gen_synthesizer(text_to_play, player.get_start_speed())?,
+            block: Vec::new(),
+            block_position: 0,
+            total_duration,
+        })
+    }
+}
+
+impl Iterator for MorseSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        while self.block_position >= self.block.len() {
+            self.block = self.synthesizer.next_block()?;
+            self.block_position = 0;
+        }
+        self.block_position += 1;
+        Some(self.block[self.block_position - 1])
+    }
+}
+
+impl rodio::Source for MorseSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        Some(self.total_duration)
+    }
+}
+
+impl Default for AudioPlayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct PlayOptions {
+    stop_fade: f32,
+    prerender: bool,
+}
+
+struct CharClock { // follows the audio, not the synthesis, which runs ahead by the sink buffers
+    char_offsets: Vec<usize>, // samples
+    current_char: Arc<AtomicUsize>,
+    started_at: Option<Instant>, // when the first samples went to the sink
+}
+
+impl CharClock {
+    fn update(&self, sink: &Sink) {
+        let Some(started_at) = self.started_at else {
+            return;
+        };
+        let position = (started_at.elapsed().as_secs_f64() * sink.speed() as f64 * SAMPLE_RATE as f64) as usize;
+        let chars_started = self.char_offsets.partition_point(|offset| *offset <= position);
+        self.current_char.store(chars_started.checked_sub(1).unwrap_or(NO_CHAR), Ordering::SeqCst);
+    }
+}
+
+#[derive(Default)]
+struct ProducerState { // whether the audio thread of the last play is running, waited for without polling
+    running: Mutex<bool>,
+    exited: std::sync::Condvar,
+    exited_async: tokio::sync::Notify,
+}
+
+impl ProducerState {
+    fn is_running(&self) -> bool {
+        *self.running.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn set_running(&self, running: bool) {
+        *self.running.lock().unwrap_or_else(PoisonError::into_inner) = running;
+        if !running {
+            self.exited.notify_all();
+            self.exited_async.notify_waiters();
+        }
+    }
+
+    fn wait(&self) { // blocks the thread, for the plays that aren't async
+        let running = self.running.lock().unwrap_or_else(PoisonError::into_inner);
+        drop(self.exited.wait_while(running, |running| *running).unwrap_or_else(PoisonError::into_inner));
+    }
+
+    async fn wait_async(&self) {
+        loop {
+            let exited = self.exited_async.notified();
+            tokio::pin!(exited);
+            exited.as_mut().enable(); // registered before the check, so an exit in between isn't missed
+            if !self.is_running() {
+                return;
+            }
+            exited.await;
+        }
+    }
+}
+
+struct BusyGuard(Arc<ProducerState>);
+
+impl Drop for BusyGuard {
+    fn drop(&mut self) {
+        self.0.set_running(false);
+    }
+}
+
+fn lock_sink(sink: &Mutex<Sink>) -> Result<MutexGuard<'_, Sink>, MorseError> { // the error is returned once, the next lock works again
+    sink.lock().map_err(|_| {
+        sink.clear_poison();
+        MorseError::LockPoisoned
+    })
+}
+
+/*
+
+    TONE EDGES:
+        Every tone is faded in and out with half a Hann window (FADE_IN and FADE_OUT, or a part
+        of the dit with FadeMode::FractionOfDit), so it starts and ends at zero whatever the phase.
+        set_envelope_fn replaces both fades with a gain over the whole tone, which doesn't have
+        to reach zero at the edges.
+        Tones start at phase 0, a zero crossing, unless set_phase_continuous is on, then they
+        go on from the phase the last one ended. set_zero_crossing_start moves that phase to the
+        nearest zero crossing (up to a quarter of a cycle, in the pause before the tone). With the
+        window it only makes the first samples smaller, it matters with a fade close to zero,
+        where the tone would otherwise start with a step.
+
+*/
+
+pub(crate) fn gen_rng(seed: Option<u64>) -> StdRng { // the same sequence for the same seed, from the OS otherwise
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_os_rng(),
+    }
+}
+
+fn apply_envelope(samples: &mut Array1<f32>, envelope: &EnvelopeFn) {
+    let last = samples.len().saturating_sub(1).max(1) as f32;
+    for (i, sample) in samples.iter_mut().enumerate() {
+        *sample *= envelope(i as f32 / last);
+    }
+}
+
+fn apply_hann_window(samples: &mut Array1<f32>, fade_in_samples: usize, fade_out_samples: usize) { // fades longer than the tone are shortened in their ratio
+    let len = samples.len();
+    let (fade_in_samples, fade_out_samples) = match fade_in_samples + fade_out_samples {
+        fades if fades > len => (fade_in_samples * len / fades, len - fade_in_samples * len / fades),
+        _ => (fade_in_samples, fade_out_samples),
+    };
+    let hann_in = Array1::linspace(0.0, PI, fade_in_samples)
+        .mapv(|x| 0.5 * (1.0 - f32::cos(x)));
+
+    let hann_out = Array1::linspace(PI, 0.0, fade_out_samples)
+        .mapv(|x| 0.5 * (1.0 - f32::cos(x)));
+
+    for i in 0..fade_in_samples {
+        samples[i] *= hann_in[i];
+    }
+
+    for i in 0..fade_out_samples {
+        samples[len - fade_out_samples + i] *= hann_out[i];
+    }
+}
+
+fn get_frequency(synth: &SynthSettings) -> f32 { // pitch shifted, with the offset of the channel
+    (synth.frequency as f32 * synth.pitch_shift + synth.frequency_offset).clamp(0.0, (SAMPLE_RATE / 2) as f32 - 1.0)
+}
+
+fn get_harmonics_count(count: Option<u32>, frequency: f32, odd_only: bool) -> u32 { // loop bound of the series in get_wave_at
+    count.unwrap_or_else(|| {
+        let max_multiplier = ((SAMPLE_RATE / 2) as f32 / frequency.max(1.0)).ceil() as u32 - 1; // strictly below Nyquist
+        if odd_only {
+            max_multiplier.div_ceil(2)
+        } else {
+            max_multiplier + 1
+        }
+    })
+}
+
+fn get_fade_times(synth: &SynthSettings, speed_to_use: f32) -> (f32, f32) { // seconds, speed_to_use is the dit length
+    match synth.fade_mode {
+        FadeMode::FixedTime => (FADE_IN, FADE_OUT),
+        FadeMode::FractionOfDit(fraction) => (speed_to_use * fraction, speed_to_use * fraction * FADE_OUT / FADE_IN),
+    }
+}
+
+fn get_wave(synth: &SynthSettings, speed_to_use: f32, duration_multiplier: i32) -> Array1::<f32> {
+    get_wave_at(synth, speed_to_use, duration_multiplier, 0.0)
+}
+
+fn get_wave_at(synth: &SynthSettings, speed_to_use: f32, duration_multiplier: i32, start_time: f32) -> Array1::<f32> { // start_time sets the phase of the wave
+    let frequency = get_frequency(synth);
+    let (fade_in, fade_out) = get_fade_times(synth, speed_to_use);
+    let fade_in_samples = (SAMPLE_RATE as f32 * fade_in) as usize;
+    let fade_out_samples = (SAMPLE_RATE as f32 * fade_out) as usize;
+    let samples_wave_count = get_samples_count(speed_to_use * duration_multiplier as f32);
+    let duration = samples_wave_count as f32 / SAMPLE_RATE as f32;
+    let t_wave = Array1::linspace(start_time, start_time + duration, samples_wave_count);
+    let mut wave = match synth.wave_type {
+        WaveType::Square => {
+            let harmonics: Vec<(u32, f32)> = (0..get_harmonics_count(synth.square_harmonics, frequency, true))
+                .map(|harmonic| (2 * harmonic + 1, 1.0 / (2 * harmonic + 1) as f32))
+                .collect();
+            get_harmonics_wave(&t_wave, frequency, &harmonics, synth.sine_lookup)
+        }
+        WaveType::Sine if synth.sine_lookup => {
+            t_wave.mapv(|t| lookup_sin_cos(frequency as f64 * t as f64).0 as f32)
+        }
+        WaveType::Sine => {
+            (2.0 * PI * frequency * t_wave).mapv(f32::sin)
+        }
+        WaveType::Triangle => {
+            let harmonics: Vec<(u32, f32)> = (0..get_harmonics_count(synth.triangle_harmonics, frequency, true))
+                .map(|harmonic| {
+                    let sign = if harmonic % 2 == 0 { 1.0 } else { -1.0 };
+                    (2 * harmonic + 1, sign / ((2 * harmonic + 1).pow(2)) as f32)
+                })
+                .collect();
+            get_harmonics_wave(&t_wave, frequency, &harmonics, synth.sine_lookup)
+        }
+        WaveType::Sawtooth => {
+            let harmonics: Vec<(u32, f32)> = (1..get_harmonics_count(synth.sawtooth_harmonics, frequency, false))
+                .map(|harmonic| (harmonic, 1.0 / harmonic as f32))
+                .collect();
+            get_harmonics_wave(&t_wave, frequency, &harmonics, synth.sine_lookup)
+        }
+    };
+
+    // Wave normalization
+    let max_amplitude = wave.iter().cloned().fold(f32::MIN, f32::max).abs();
+    if max_amplitude > 0.0 {
+        wave /= max_amplitude;
+    }
+
+    // Loudness matching, gain is capped so peaks stay in range
+    if synth.loudness_match && !wave.is_empty() {
+        let rms = (wave.mapv(|x| x * x).sum() / wave.len() as f32).sqrt();
+        if rms > 0.0 {
+            wave *= (LOUDNESS_RMS / rms).min(1.0);
+        }
+    }
+
+    match &synth.envelope {
+        Some(envelope) => apply_envelope(&mut wave, envelope),
+        None => apply_hann_window(&mut wave, fade_in_samples, fade_out_samples),
+    }
+
+    wave
+}
+
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl Biquad {
+    fn lowpass(cutoff_hz: f32, sample_rate: u32) -> Biquad { // RBJ cookbook low-pass with Butterworth Q
+        let cutoff_hz = cutoff_hz.clamp(1.0, sample_rate as f32 * 0.49);
+        let w0 = 2.0 * PI * cutoff_hz / sample_rate as f32;
+        let alpha = w0.sin() / (2.0 * std::f32::consts::FRAC_1_SQRT_2);
+        let cos_w0 = w0.cos();
+        let a0 = 1.0 + alpha;
+        Biquad {
+            b0: (1.0 - cos_w0) / 2.0 / a0,
+            b1: (1.0 - cos_w0) / a0,
+            b2: (1.0 - cos_w0) / 2.0 / a0,
+            a1: -2.0 * cos_w0 / a0,
+            a2: (1.0 - alpha) / a0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            let x0 = *sample;
+            let y0 = self.b0 * x0 + self.b1 * self.x1 + self.b2 * self.x2 - self.a1 * self.y1 - self.a2 * self.y2;
+            self.x2 = self.x1;
+            self.x1 = x0;
+            self.y2 = self.y1;
+            self.y1 = y0;
+            *sample = y0;
+        }
+    }
+}
+
+struct Noise {
+    noise_type: NoiseType,
+    amplitude: f32,
+    rng: StdRng,
+    pink: [f32; 7],
+    brown: f32,
+}
+
+impl Noise {
+    fn new(snr_db: f32, noise_type: NoiseType, seed: Option<u64>) -> Noise {
+        Noise {
+            noise_type,
+            amplitude: std::f32::consts::FRAC_1_SQRT_2 * 10.0_f32.powf(-snr_db / 20.0), // RMS of a full scale sine
+            rng: gen_rng(seed),
+            pink: [0.0; 7],
+            brown: 0.0,
+        }
+    }
+
+    fn next_sample(&mut self) -> f32 { // unit RMS
+        let white: f32 = self.rng.random_range(-1.0..=1.0);
+        match self.noise_type {
+            NoiseType::White => white * 1.732,
+            NoiseType::Pink => { // Paul Kellet's filter
+                let b = &mut self.pink;
+                b[0] = 0.99886 * b[0] + white * 0.0555179;
+                b[1] = 0.99332 * b[1] + white * 0.0750759;
+                b[2] = 0.96900 * b[2] + white * 0.153852;
+                b[3] = 0.86650 * b[3] + white * 0.3104856;
+                b[4] = 0.55000 * b[4] + white * 0.5329522;
+                b[5] = -0.7616 * b[5] - white * 0.0168980;
+                let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+                b[6] = white * 0.115926;
+                pink * 0.56
+            }
+            NoiseType::Brown => {
+                self.brown = (self.brown + 0.02 * white) / 1.02;
+                self.brown * 17.4
+            }
+        }
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            *sample += self.next_sample() * self.amplitude;
+        }
+    }
+}
+
+fn get_silence(speed_to_use: f32, duration_multiplier: i32) -> Vec<f32> {
+    let silence: Vec<f32> = vec![0.0; get_samples_count(speed_to_use * duration_multiplier as f32)];
+    silence
+}
+
+fn get_samples_count(duration: f32) -> usize {
+    (SAMPLE_RATE as f32 * duration.clamp(0.0, MAX_WAVE_DURATION)) as usize
+}
+
+fn get_harmonics_wave(t_wave: &Array1<f32>, frequency: f32, harmonics: &[(u32, f32)], sine_lookup: bool) -> Array1<f32> { // (multiplier, amplitude) pairs, summed in one pass
+    // sin((k + step) * x) = 2 * cos(step * x) * sin(k * x) - sin((k - step) * x), only odd multipliers are stepped by 2
+    let step: u32 = if harmonics.iter().all(|(multiplier, _)| multiplier % 2 == 1) { 2 } else { 1 };
+    let max_multiplier = harmonics.iter().map(|(multiplier, _)| *multiplier).max().unwrap_or(0);
+    let mut amplitudes = vec![0.0_f64; (max_multiplier / step + 1) as usize]; // amplitudes[n] is for multiplier 1 + n * step
+    for (multiplier, amplitude) in harmonics.iter().filter(|(multiplier, _)| *multiplier > 0) {
+        amplitudes[((multiplier - 1) / step) as usize] += *amplitude as f64;
+    }
+
+    let t_wave = t_wave.to_vec();
+    let mut wave = vec![0.0_f32; t_wave.len()];
+    for (t_chunk, wave_chunk) in t_wave.chunks(HARMONICS_LANES).zip(wave.chunks_mut(HARMONICS_LANES)) { // independent lanes vectorize
+        let mut sin_previous = [0.0_f64; HARMONICS_LANES];
+        let mut sin_now = [0.0_f64; HARMONICS_LANES];
+        let mut cos_step = [0.0_f64; HARMONICS_LANES];
+        for (lane, t) in t_chunk.iter().enumerate() {
+            let (sin_x, cos_x) = if sine_lookup {
+                lookup_sin_cos(frequency as f64 * *t as f64)
+            } else {
+                (2.0 * std::f64::consts::PI * frequency as f64 * *t as f64).sin_cos()
+            };
+            sin_now[lane] = sin_x;
+            (sin_previous[lane], cos_step[lane]) = if step == 2 { (-sin_x, 2.0 * cos_x * cos_x - 1.0) } else { (0.0, cos_x) };
+        }
+        let mut samples = [0.0_f64; HARMONICS_LANES];
+        for amplitude in &amplitudes {
+            for lane in 0..HARMONICS_LANES {
+                samples[lane] += amplitude * sin_now[lane];
+                let sin_next = 2.0 * cos_step[lane] * sin_now[lane] - sin_previous[lane];
+                sin_previous[lane] = sin_now[lane];
+                sin_now[lane] = sin_next;
+            }
+        }
+        for (sample, value) in wave_chunk.iter_mut().zip(samples) {
+            *sample = value as f32;
+        }
+    }
+    Array1::from_vec(wave)
+}
+
+fn lookup_sin_cos(cycles: f64) -> (f64, f64) { // linear interpolation in a table of one period, error below 5e-6
+    let table = SINE_TABLE.get_or_init(|| {
+        (0..=SINE_TABLE_SIZE).map(|i| (2.0 * std::f64::consts::PI * i as f64 / SINE_TABLE_SIZE as f64).sin()).collect()
+    });
+    let lookup = |cycles: f64| {
+        let position = cycles.rem_euclid(1.0) * SINE_TABLE_SIZE as f64;
+        let index = (position as usize).min(SINE_TABLE_SIZE - 1);
+        let fraction = position - index as f64;
+        table[index] + (table[index + 1] - table[index]) * fraction
+    };
+    (lookup(cycles), lookup(cycles + 0.25))
+}
+
+fn get_waves(synth: &SynthSettings, speed_to_use: f32, actions_length: &HashMap<char, (i32, i32)>) -> HashMap<char, Array1<f32>> {
+    actions_length.iter()
+        .filter(|(_, (action, _))| *action == get_tone_action(synth))
+        .map(|(element, (_, duration_multiplier))| (*element, get_wave(synth, speed_to_use, *duration_multiplier)))
+        .collect()
+}
+
+fn get_silences(synth: &SynthSettings, speed_to_use: f32, actions_length: &HashMap<char, (i32, i32)>) -> HashMap<char, Vec<f32>> {
+    actions_length.iter()
+        .filter(|(_, (action, _))| *action == 1 - get_tone_action(synth) || *action == 3)
+        .map(|(element, action_description)| (*element, get_silence(get_element_duration(action_description, speed_to_use), 1)))
+        .collect()
+}
+
+fn get_element_gain(synth: &SynthSettings, element: char) -> f32 {
+    match element {
+        '.' => synth.dot_gain,
+        '-' => synth.dash_gain,
+        _ => 1.0,
+    }
+}
+
+fn get_tone_action(synth: &SynthSettings) -> i32 { // action of the elements sounding as a tone
+    if synth.inverted { 1 } else { 0 }
+}
+
+fn get_element_duration(action_description: &(i32, i32), speed_to_use: f32) -> f32 {
+    if action_description.0 == 3 {
+        action_description.1 as f32 / 1000.0
+    } else {
+        speed_to_use * action_description.1 as f32
+    }
+}
+
+/*
+    DESCRIPTION OF TONES:
+        . - Dot, 1 unit
+        - - Dash, 3 units
+        Other tones can be added with set_element_units and used in patterns given to set_char_code
+
+    DESCRIPTION OF PAUSES:
+        * - Pause beetween dots or dashes
+        $ - Pause beetween characters
+        / - Pause beetween words
+        % - Pause beetween groups, used instead of / with group gaps on
+        ! - Pause beetween digits of the announced speed, used instead of $ with an announcement gap set
+        + - Pause beetween merged characters, used instead of $, as long as * or empty
+        ~ - Pause beetween repeats, fixed length in milliseconds
+        # - Pause beetween a word and its echo, fixed length in milliseconds
+        ^ - Pause beetween the start part and the main text, fixed length in milliseconds
+        < - Silence before everything, fixed length in milliseconds, only with a pad set
+        > - Silence after everything, fixed length in milliseconds, only with a pad set
+
+    ACTIONS: 0 - tone, 1 - pause, 2 - speed change, 3 - pause with fixed length
+
+    The fixed pauses (~ # ^ < >) aren't kept in the element table, their lengths are settings
+    of the player (set_repeat, set_word_echo, set_pre_text_gap and set_pad), added to a copy
+    of the table when timing or playing.
+
+    Element lengths are copied when playing starts, changing them (set_delay, set_element_units
+    and the gap setters) while playing applies from the next play on.
+
+*/
+
+fn drain_in_real_time<S: rodio::Source<Item = f32>>(mut output: S) { // plays the part of an output device for a headless sink or pool, ends with the output
+    let chunk = (SAMPLE_RATE / 100) as u64;
+    let started_at = Instant::now();
+    let mut drained: u64 = 0;
+    loop {
+        let channels = rodio::Source::channels(&output).max(1) as u64; // samples of all channels make one frame
+        for _ in 0..chunk * channels {
+            if output.next().is_none() {
+                return;
+            }
+        }
+        drained += chunk;
+        let due = started_at + Duration::from_secs_f64(drained as f64 / SAMPLE_RATE as f64);
+        thread::sleep(due.saturating_duration_since(Instant::now()));
+    }
+}
+
+fn fade_out(sink: &Sink, secs: f32) { // ramps the volume down and clears the sink, stop waits for it as the sink is locked
+    if secs <= 0.0 {
+        return;
+    }
+    let volume = sink.volume();
+    let steps = (secs / 0.005).ceil() as u32;
+    for step in 1..=steps {
+        sink.set_volume(volume * (1.0 - step as f32 / steps as f32));
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    sink.clear();
+    sink.set_volume(volume);
+}
+
+fn play_audio(synthesizer: &mut Synthesizer, mut right_synthesizer: Option<&mut Synthesizer>, sink: &Sink, stop_flag: &Arc<AtomicBool>, underrun_callback: Option<&UnderrunCallback>,
+    char_clock: &mut CharClock, options: PlayOptions) -> (usize, bool) { // samples sent (per channel) and whether the end was reached, stereo with a right channel synthesizer
+    let PlayOptions { stop_fade, prerender } = options;
+    let channels: u16 = if right_synthesizer.is_some() { 2 } else { 1 };
+    let mut first_buffer = true;
+    let mut samples = 0;
+    let mut prerendered = Vec::<f32>::new();
+    let completed = synthesize(synthesizer, stop_flag, &mut |sound_signal| {
+        let sound_signal = match right_synthesizer.as_deref_mut() { // both synthesizers cut blocks at the same elements
+            Some(right_synthesizer) => interleave(&sound_signal, &right_synthesizer.next_block().unwrap_or_default()),
+            None => sound_signal,
+        };
+        if prerender {
+            prerendered.extend(sound_signal);
+            return true;
+        }
+        if sink.len() == 0 && !first_buffer {
+            if let Some(callback) = underrun_callback {
+                callback();
+            }
+        }
+        first_buffer = false;
+        loop {
+            char_clock.update(sink);
+            if sink.len() <= SINK_BUFFER_SIZE as usize {
+                break;
+            }
+            if stop_flag.load(Ordering::SeqCst) {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        samples += sound_signal.len() / channels as usize;
+        sink.append(rodio::buffer::SamplesBuffer::new(channels, SAMPLE_RATE, sound_signal));
+        char_clock.started_at.get_or_insert_with(Instant::now);
+        true
+    });
+    if !completed {
+        fade_out(sink, stop_fade);
+        return (samples, false);
+    }
+    if prerender {
+        samples = prerendered.len() / channels as usize;
+        sink.append(rodio::buffer::SamplesBuffer::new(channels, SAMPLE_RATE, prerendered));
+        char_clock.started_at = Some(Instant::now());
+    }
+
+    while sink.len() != 0 {
+        char_clock.update(sink);
+        if stop_flag.load(Ordering::SeqCst) {
+            fade_out(sink, stop_fade);
+            return (samples, false);
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    (samples, true)
+}
+
+fn interleave(left: &[f32], right: &[f32]) -> Vec<f32> { // the longer channel sets the length, the other one is padded with silence
+    (0..left.len().max(right.len()))
+        .flat_map(|i| [left.get(i).copied().unwrap_or(0.0), right.get(i).copied().unwrap_or(0.0)])
+        .collect()
+}
+
+fn synthesize(synthesizer: &mut Synthesizer, stop_flag: &AtomicBool, output: &mut dyn FnMut(Vec<f32>) -> bool) -> bool { // false if stopped before the end
+    let mut sound_signal = Vec::<f32>::new();
+    while !synthesizer.is_finished() {
+        if synthesizer.position.is_multiple_of(STOP_CHECK_INTERVAL) && stop_flag.load(Ordering::SeqCst) {
+            return false;
+        }
+        if synthesizer.next_element(&mut sound_signal) && !output(std::mem::take(&mut sound_signal)) {
+            return false;
+        }
+    }
+    true
+}
+
+type ElementSounds = (HashMap<char, Array1<f32>>, HashMap<char, Vec<f32>>); // waves and silences of the elements at one speed
+
+struct Synthesizer {
+    text: TextToPlay,
+    actions_length: HashMap<char, (i32, i32)>,
+    synth: SynthSettings,
+    position: usize,
+    speed_steps: SpeedSteps,
+    waves: HashMap<char, Array1<f32>>,
+    silences: HashMap<char, Vec<f32>>,
+    sounds_by_speed: HashMap<u32, ElementSounds>, // of the other speeds played, by the bits of their dot duration
+    lowpass: Option<Biquad>,
+    noise: Option<Noise>,
+    joined_until: usize, // tones before this position are already added as a part of a joined tone
+    fist_rng: StdRng,
+    phase: f64, // in cycles, where the last tone ended
+    finish_flag: Option<Arc<AtomicBool>>, // only the synthesizer deciding the cut has it
+    end_text_cut: Arc<OnceLock<usize>>, // shared with the handle and the right channel
+    cut_applied: bool,
+}
+
+impl Synthesizer {
+    fn new(text: TextToPlay, base_duration: f32, speed: f32, actions_length: HashMap<char, (i32, i32)>, synth: SynthSettings) -> Result<Synthesizer, MorseError> {
+        ElementWalk::new(&text.elements, &text.speed_pattern, base_duration, speed, &actions_length).check()?;
+        let speed_steps = SpeedSteps::new(base_duration, speed);
+        Ok(Synthesizer {
+            waves: get_waves(&synth, speed_steps.dot_duration, &actions_length),
+            silences: get_silences(&synth, speed_steps.dot_duration, &actions_length),
+            sounds_by_speed: HashMap::new(),
+            lowpass: synth.lowpass.map(|cutoff_hz| Biquad::lowpass(cutoff_hz, SAMPLE_RATE)),
+            noise: synth.noise.map(|(snr_db, noise_type)| Noise::new(snr_db, noise_type, synth.noise_seed)),
+            fist_rng: gen_rng(synth.fist_seed),
+            text,
+            actions_length,
+            synth,
+            position: 0,
+            speed_steps,
+            joined_until: 0,
+            phase: 0.0,
+            finish_flag: None,
+            end_text_cut: Arc::new(OnceLock::new()),
+            cut_applied: false,
+        })
+    }
+
+    fn apply_end_text_cut(&mut self) {
+        if self.cut_applied || self.is_finished() {
+            return;
+        }
+        let cut = match &self.finish_flag {
+            Some(finish_flag) if finish_flag.load(Ordering::SeqCst) => Some(*self.end_text_cut.get_or_init(|| self.get_end_text_cut())),
+            _ => self.end_text_cut.get().copied(),
+        };
+        let Some(cut) = cut else {
+            return;
+        };
+        let elements = &mut self.text.elements;
+        let pad = elements.last() == Some(&'>');
+        elements.truncate(cut);
+        elements.extend(END_TEXT);
+        if pad {
+            elements.push('>');
+        }
+        for range in self.text.main_text.iter_mut() {
+            *range = range.start.min(cut)..range.end.min(cut);
+        }
+        self.cut_applied = true;
+    }
+
+    fn get_end_text_cut(&self) -> usize { // the rest of the character sounding plays before END_TEXT, an END_TEXT already begun is cut at its start, so it goes on unchanged
+        let elements = &self.text.elements;
+        let end_text_begun = elements.windows(END_TEXT.len()).enumerate()
+            .find(|(start, window)| *window == END_TEXT && (*start + 1..=*start + END_TEXT.len()).contains(&self.position));
+        if let Some((start, _)) = end_text_begun {
+            return start;
+        }
+        (self.position.max(self.joined_until)..elements.len())
+            .find(|i| elements[*i] != '*' && matches!(self.actions_length.get(&elements[*i]), Some((1 | 3, _))))
+            .unwrap_or(elements.len())
+    }
+
+    fn is_finished(&self) -> bool {
+        self.position >= self.text.elements.len()
+    }
+
+    fn get_played_element_count(&self) -> usize { // tones and pauses, as count_elements
+        count_elements(&self.text.elements[..self.position.min(self.text.elements.len())], &self.actions_length)
+    }
+
+    fn next_block(&mut self) -> Option<Vec<f32>> { // at least BUFFER_DURATION of audio, less at the end
+        if self.is_finished() {
+            return None;
+        }
+        let mut sound_signal = Vec::<f32>::new();
+        while !self.next_element(&mut sound_signal) {}
+        Some(sound_signal)
+    }
+
+    fn next_element(&mut self, sound_signal: &mut Vec<f32>) -> bool { // true if sound_signal is ready to be played
+        let i = self.position;
+        let element = self.text.elements[i];
+        let synth = &self.synth;
+        let action: i32 = self.actions_length.get(&element).map_or(-1, |action_description| action_description.0); // unknown elements are skipped
+        let tone_action = get_tone_action(synth);
+
+        if action == tone_action && i >= self.joined_until {
+            let mut gain = if self.text.is_main_text(i) { 1.0 } else { synth.marker_volume } * get_element_gain(synth, element);
+            if synth.fist_amount > 0.0 {
+                gain *= (1.0 + self.fist_rng.random_range(-synth.fist_amount..=synth.fist_amount)).max(0.0);
+            }
+            let mut joined_units = 0;
+            self.joined_until = i;
+            while synth.smooth_joins && self.joined_until < self.text.elements.len() {
+                match self.actions_length.get(&self.text.elements[self.joined_until]) {
+                    Some(&(action, units)) if action == tone_action => joined_units += units,
+                    _ => break,
+                }
+                self.joined_until += 1;
+            }
+            if synth.phase_continuous {
+                let units = if self.joined_until > i + 1 { joined_units } else { self.actions_length[&element].1 };
+                let frequency = get_frequency(synth);
+                if synth.zero_crossing_start { // every wave type is a sum of sines, so all of them cross zero every half cycle
+                    self.phase = (self.phase * 2.0).round() / 2.0;
+                }
+                let wave = get_wave_at(synth, self.speed_steps.dot_duration, units, (self.phase / frequency.max(1.0) as f64) as f32);
+                self.phase = (self.phase + frequency as f64 * wave.len() as f64 / SAMPLE_RATE as f64).fract();
+                sound_signal.extend(wave.iter().map(|sample| sample * gain));
+            } else if self.joined_until > i + 1 {
+                sound_signal.extend(get_wave(synth, self.speed_steps.dot_duration, joined_units).iter().map(|sample| sample * gain));
+            } else {
+                sound_signal.extend(self.waves[&element].iter().map(|sample| sample * gain));
+            }
+        }
+        else if action == 1 - tone_action || action == 3 {
+            sound_signal.extend_from_slice(&self.silences[&element]);
+        }
+        else if action == 2 {
+            let previous_dot_duration = self.speed_steps.dot_duration;
+            self.speed_steps.step(&self.text.speed_pattern);
+            let dot_duration = self.speed_steps.dot_duration;
+            if dot_duration.to_bits() != previous_dot_duration.to_bits() {
+                let previous_sounds = (std::mem::take(&mut self.waves), std::mem::take(&mut self.silences));
+                self.sounds_by_speed.insert(previous_dot_duration.to_bits(), previous_sounds);
+                (self.waves, self.silences) = self.sounds_by_speed.remove(&dot_duration.to_bits())
+                    .unwrap_or_else(|| (get_waves(synth, dot_duration, &self.actions_length), get_silences(synth, dot_duration, &self.actions_length)));
+            }
+        }
+
+        self.position += 1;
+        self.apply_end_text_cut();
+        if sound_signal.len() >= get_samples_count(BUFFER_DURATION) || self.is_finished() {
+            if let Some(noise) = self.noise.as_mut() { // before the filter, like band noise before a receiver filter
+                noise.process(sound_signal);
+            }
+            if let Some(filter) = self.lowpass.as_mut() {
+                filter.process(sound_signal);
+            }
+            if self.synth.output_gain != 1.0 {
+                sound_signal.iter_mut().for_each(|sample| *sample *= self.synth.output_gain);
+            }
+            return true;
+        }
+        false
+    }
+}
+
+fn gen_speed_announcement(speed: f32, announcement_gap: bool) -> Vec<char> { // speed rounded half away from zero, 124.5 is announced as 125
+    let speed_chars_vec: Vec<char> = (speed.round() as i32).to_string().chars().collect();
+    let (_, announcement) = gen_audio_prev_vec(&speed_chars_vec, 100.0, 100.0, 100.0, SpeedModificationType::None, 10, &GeneratorSettings::default());
+    if announcement_gap {
+        announcement.iter().map(|element| if *element == '$' { '!' } else { *element }).collect()
+    }
+    else {
+        announcement
+    }
+}
+
+fn gen_countdown(from: u8, speed: f32, announcement_speed: Option<f32>) -> (Vec<f32>, Vec<char>) { // with the word gap after it
+    if from == 0 {
+        return (Vec::new(), Vec::new());
+    }
+    let countdown_chars: Vec<char> = (1..=from).rev().map(|number| number.to_string()).collect::<Vec<String>>().join(" ").chars().collect();
+    let (_, countdown) = gen_audio_prev_vec(&countdown_chars, 100.0, 100.0, 100.0, SpeedModificationType::None, 10, &GeneratorSettings::default());
+    let (speed_pattern, mut countdown) = gen_at_announcement_speed(countdown, speed, announcement_speed);
+    countdown.push('/');
+    (speed_pattern, countdown)
+}
+
+fn gen_announcement_part(speed: f32, announcement_speed: Option<f32>, announcement_gap: bool) -> (Vec<f32>, Vec<char>) {
+    gen_at_announcement_speed(gen_speed_announcement(speed, announcement_gap), speed, announcement_speed)
+}
+
+fn gen_at_announcement_speed(elements: Vec<char>, speed: f32, announcement_speed: Option<f32>) -> (Vec<f32>, Vec<char>) {
+    let mut speed_pattern: Vec<f32> = Vec::new();
+    let mut announcement: Vec<char> = Vec::new();
+    if let Some(announcement_speed) = announcement_speed {
+        speed_pattern.push(announcement_speed);
+        announcement.push('|');
+    }
+    announcement.extend(elements);
+    if announcement_speed.is_some() { // back to the start speed before the pause after the number
+        speed_pattern.push(speed);
+        announcement.push('|');
+    }
+    (speed_pattern, announcement)
+}
+
+fn gen_start_part_prev_vec(text_additions: TextAdditions, text_type: TextType, speed: f32, announcement_speed: Option<f32>, announcement_gap: bool) -> (Vec<f32>, Vec<char>) {
+    let mut start_part: Vec<char> = Vec::new();
+    let mut speed_pattern: Vec<f32> = Vec::new();
+    match text_additions {
+        TextAdditions::None => {
+
+        },
+        TextAdditions::Training => {
+            start_part.extend(START_TEXT);
+        }
+        TextAdditions::Competitions => {
+            if text_type == TextType::Digits {
+                start_part.extend(START_TEXT_COMPETITIONS_DIGITS);
+                let (announcement_speed_pattern, announcement) = gen_announcement_part(speed, announcement_speed, announcement_gap);
+                speed_pattern.extend(announcement_speed_pattern);
+                start_part.extend(announcement);
+                start_part.push('/');
+                start_part.extend(START_TEXT);
+            }
+            else {
+                start_part.extend(START_TEXT_COMPETITIONS_LETTERS);
+                let (announcement_speed_pattern, announcement) = gen_announcement_part(speed, announcement_speed, announcement_gap);
+                speed_pattern.extend(announcement_speed_pattern);
+                start_part.extend(announcement);
+                start_part.push('/');
+                start_part.extend(START_TEXT);
+            }
+        },
+    }
+    (speed_pattern, start_part)
+}
+
+fn gen_audio_prev_vec(text: &[char], speed: f32, min_speed: f32, max_speed: f32, speed_modification_type: SpeedModificationType, modification_len: i32,
+    generator: &GeneratorSettings) -> (Vec<f32>, Vec<char>) {
+    let mut audio_vec = Vec::<char>::new();
+    let mut speed_pattern = Vec::<f32>::new();
+    let speed_difference = max_speed - min_speed;
+    let modification_len = modification_len * 5;
+    let mut char_now: i32 = 0;
+    let mut word_start: (usize, usize) = (0, 0); // positions of the current word in audio_vec and speed_pattern
+    let text: Vec<char> = text.iter().collect::<String>() // any whitespace run is one word gap, leading and trailing ones are dropped
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .chars()
+        .collect();
+    let word_gap = if generator.group_gap && is_grouped(&text) { '%' } else { '/' };
+    let char_gap = if generator.merge_chars { '+' } else { '$' };
+    let mut word_index: usize = 0;
+    let mut speed_now = speed; // without modifications, changed by word speeds
+    let element_gaps = generator.element_gaps || !generator.merge_chars;
+
+    for (i, element) in text.iter().enumerate() {
+        let word_speed = generator.word_speeds.get(&word_index).copied();
+        if *element != ' ' && (i == 0 || text[i - 1] == ' ') && speed_modification_type == SpeedModificationType::None {
+            let speed_of_word = word_speed.unwrap_or(speed);
+            if speed_of_word != speed_now {
+                speed_pattern.push(speed_of_word);
+                audio_vec.push('|');
+                speed_now = speed_of_word;
+            }
+        }
+        if *element != ' ' && speed_modification_type != SpeedModificationType::None {
+            let speed_on_char: f32 = match speed_modification_type {
+                SpeedModificationType::Speedup => {
+                    speed_difference / (modification_len - 1) as f32 * char_now as f32 + min_speed
+                }
+                SpeedModificationType::Slowing => {
+                    max_speed - (speed_difference / (modification_len - 1) as f32 * char_now as f32)
+                }
+                SpeedModificationType::Zigzag => {
+                    if char_now < modification_len / 2 {
+                        speed_difference / ((modification_len / 2) - 1) as f32 * char_now as f32 + min_speed
+                    }
+                    else {
+                        max_speed - (speed_difference / ((modification_len / 2) - 1) as f32 * (char_now - modification_len / 2) as f32)
+                    }
+                }
+                _ => {
+                    panic!("Invalid Modification type");
+                },
+            };
+            let speed_on_char = word_speed.unwrap_or(speed_on_char);
+
+            speed_pattern.push(if generator.emphasis_chars.contains(element) { speed_on_char * generator.emphasis_factor } else { speed_on_char });
+
+            char_now += 1;
+            if char_now == modification_len {
+                char_now = 0;
+            }
+
+            audio_vec.push('|'); // char, that inform play function to recalculate speed
+        }
+        let emphasized = speed_modification_type == SpeedModificationType::None && generator.emphasis_chars.contains(element);
+        if emphasized {
+            speed_pattern.push(speed_now * generator.emphasis_factor);
+            audio_vec.push('|');
+        }
+        let element = if generator.cut_numbers { generator.cut_number_table.get(element).unwrap_or(element) } else { element };
+        let morse_code = match (generator.code_table.get(element), generator.unknown_policy) {
+            (None, UnknownPolicy::Substitute(substitute)) if *element != ' ' => generator.code_table.get(&substitute),
+            (morse_code, _) => morse_code,
+        };
+        if let Some(morse_code) = morse_code {
+            for (n, morse_char) in morse_code.chars().enumerate() {
+                audio_vec.push(morse_char);
+                if n+1 != morse_code.len() && element_gaps {
+                    audio_vec.push('*');
+                }
+            }
+        }
+        if emphasized { // back to the normal speed before the pause after the character
+            speed_pattern.push(speed_now);
+            audio_vec.push('|');
+        }
+
+        if generator.word_echo && (*element == ' ' || i == text.len() - 1) && audio_vec.len() > word_start.0 {
+            let word_end = if *element == ' ' { audio_vec.len() - 1 } else { audio_vec.len() }; // without the trailing character pause
+            let word: Vec<char> = audio_vec[word_start.0..word_end].to_vec();
+            let word_speed_pattern: Vec<f32> = speed_pattern[word_start.1..].to_vec();
+            audio_vec.truncate(word_end);
+            audio_vec.push('#');
+            audio_vec.extend(word);
+            speed_pattern.extend(word_speed_pattern);
+            if *element == ' ' {
+                audio_vec.push('$');
+            }
+        }
+
+        if *element != ' ' && i != text.len() - 1 {
+            audio_vec.push(char_gap);
+        }
+        else if *element == ' ' {
+            let audio_vec_len = audio_vec.len();
+            if char_now == 0 && speed_modification_type != SpeedModificationType::None { // if enabled modification, make latest silence long
+                speed_pattern.push(min_speed);
+                audio_vec[audio_vec_len - 1] = '|';
+                audio_vec.push(word_gap);
+            }
+            else {
+                audio_vec[audio_vec_len - 1] = word_gap;
+            }
+            word_start = (audio_vec.len(), speed_pattern.len());
+            word_index += 1;
+        }
+    }
+
+    (speed_pattern, audio_vec)
+}
+
+fn get_dot_duration(base_duration: f32, speed: f32) -> f32 { // calculating absolute speed of text
+    base_duration * 100.0 / speed
+}
+
+fn get_text_type_for(text_type: TextType, text: &[char]) -> TextType { // resolves TextType::Auto
+    if text_type != TextType::Auto {
+        return text_type;
+    }
+    let has_letters = text.iter().any(|ch| ch.is_alphabetic());
+    let has_digits = text.iter().any(|ch| ch.is_ascii_digit());
+    match (has_letters, has_digits) {
+        (true, true) => TextType::Mixed,
+        (false, true) => TextType::Digits,
+        _ => TextType::Letters,
+    }
+}
+
+fn get_base_duration_for(text_type: TextType, letters_duration: f32, digits_duration: f32, mixed_duration: f32) -> f32 {
+    match text_type {
+        TextType::Digits => digits_duration,
+        TextType::Mixed => mixed_duration,
+        _ => letters_duration,
+    }
+}
+
+fn get_start_speed_for(speed_modification_type: SpeedModificationType, speed: f32, min_speed: f32, max_speed: f32) -> f32 { // modifications start from their edge speed
+    match speed_modification_type {
+        SpeedModificationType::Speedup | SpeedModificationType::Zigzag => min_speed,
+        SpeedModificationType::Slowing => max_speed,
+        SpeedModificationType::None => speed,
+    }
+}
+
+fn get_speed_for_wpm(base_duration: f32, wpm: f32) -> Result<f32, MorseError> { // PARIS is 50 dots long
+    if !wpm.is_finite() || wpm <= 0.0 {
+        return Err(MorseError::InvalidSpeed(wpm));
+    }
+    Ok(base_duration * 100.0 * 50.0 * wpm / 60.0)
+}
+
+fn is_grouped(text: &[char]) -> bool { // at least two words, all of the same length
+    let words: Vec<&[char]> = text.split(|ch| *ch == ' ').collect();
+    words.len() > 1 && words.iter().all(|word| word.len() == words[0].len())
+}
+
+/*
+
+    ELEMENT WALK:
+        The timings of a text come from one walk over its elements, with the same speed
+        changes and durations as the synthesizer. Every element is yielded with its start,
+        duration and action, unknown elements with action -1 and no duration. A character
+        of the main text begins at its first tone and ends at the next pause that isn't
+        the one inside a character, so when inverted it spans the same elements, silent
+        ones included.
+
+*/
 
-        if action_discription.unwrap().0 == 2 {
-            speed_to_use = get_speed_from_text_type(text_type, speed_pattern.unwrap()[char_now]);
-            char_now += 1;
+#[derive(Clone, Copy)]
+struct TimedElement {
+    position: usize, // in the elements
+    element: char,
+    start: f32, // seconds
+    duration: f32,
+    action: i32,
+    units: i32,
+    speed_changes: usize, // up to and including this element
+    speed: f32, // after this element
+}
+
+#[derive(Clone, Copy)]
+struct SpeedSteps { // speed changes of a text as they're played, the walk and the synthesizer step the same way
+    base_duration: f32,
+    speed_changes: usize,
+    speed: f32,
+    dot_duration: f32,
+}
+
+impl SpeedSteps {
+    fn new(base_duration: f32, speed: f32) -> SpeedSteps {
+        SpeedSteps { base_duration, speed_changes: 0, speed, dot_duration: get_dot_duration(base_duration, speed) }
+    }
+
+    fn step(&mut self, speed_pattern: &[f32]) { // at a speed change element
+        self.speed = speed_pattern[self.speed_changes];
+        self.dot_duration = get_dot_duration(self.base_duration, self.speed);
+        self.speed_changes += 1;
+    }
+}
+
+struct ElementWalk<'a> {
+    elements: &'a [char],
+    speed_pattern: &'a [f32],
+    actions_length: &'a HashMap<char, (i32, i32)>,
+    position: usize,
+    time: f32,
+    speed_steps: SpeedSteps,
+}
+
+impl<'a> ElementWalk<'a> {
+    fn new(elements: &'a [char], speed_pattern: &'a [f32], base_duration: f32, speed: f32, actions_length: &'a HashMap<char, (i32, i32)>) -> ElementWalk<'a> {
+        ElementWalk { elements, speed_pattern, actions_length, position: 0, time: 0.0, speed_steps: SpeedSteps::new(base_duration, speed) }
+    }
+
+    fn check(mut self) -> Result<(), MorseError> { // every element has a length a wave can have, so the timings and the samples agree
+        while let Some(timed_element) = self.next() {
+            let dot_duration = self.speed_steps.dot_duration;
+            if !dot_duration.is_finite() || dot_duration <= 0.0 {
+                return Err(MorseError::InvalidSpeed(timed_element.speed));
+            }
+            if !(0.0..=MAX_WAVE_DURATION).contains(&timed_element.duration) {
+                return Err(MorseError::InvalidDuration(Duration::try_from_secs_f32(timed_element.duration).unwrap_or_default()));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for ElementWalk<'_> {
+    type Item = TimedElement;
+
+    fn next(&mut self) -> Option<TimedElement> {
+        let element = *self.elements.get(self.position)?;
+        let (action, units) = self.actions_length.get(&element).copied().unwrap_or((-1, 0)); // unknown elements are skipped
+        let duration = get_element_duration(&(action, units), self.speed_steps.dot_duration);
+        if action == 2 {
+            self.speed_steps.step(self.speed_pattern);
         }
+        let timed_element = TimedElement { position: self.position, element, start: self.time, duration, action, units, speed_changes: self.speed_steps.speed_changes, speed: self.speed_steps.speed };
+        self.time += duration;
+        self.position += 1;
+        Some(timed_element)
+    }
+}
+
+fn track_char(timed_element: &TimedElement, text: &TextToPlay, in_char: &mut bool) -> bool { // true at the first tone of a main text character
+    match timed_element.action {
+        0 if !*in_char && text.is_main_text(timed_element.position) => {
+            *in_char = true;
+            true
+        }
+        1 | 3 if timed_element.element != '*' => { // any pause but the one inside a character
+            *in_char = false;
+            false
+        }
+        _ => false,
+    }
+}
 
-        if *element == '$' || *element == '/' {
+fn get_time_and_timings(audio_prev_vec: &[char], base_duration: f32, speed: f32, speed_pattern: &[f32], actions_length: &HashMap<char, (i32, i32)>) -> Result<(f32, Vec<Duration>), MorseError> {
+    ElementWalk::new(audio_prev_vec, speed_pattern, base_duration, speed, actions_length).check()?;
+    let mut time_pattern_vec = vec![Duration::from_millis(0)];
+    let mut duration: f32 = 0.0;
+    for timed_element in ElementWalk::new(audio_prev_vec, speed_pattern, base_duration, speed, actions_length) {
+        duration = timed_element.start + timed_element.duration;
+        if matches!(timed_element.element, '$' | '!' | '+' | '/' | '%' | '~' | '#') {
             time_pattern_vec.push(Duration::from_millis((duration * 1000.0) as u64));
         }
     }
-    (duration, time_pattern_vec)
+    Ok((duration, time_pattern_vec))
+}
+
+#[cfg(feature = "serial")]
+fn sleep_until(deadline: Instant, stop_flag: &AtomicBool) -> bool { // false if stopped before the deadline
+    loop {
+        if stop_flag.load(Ordering::SeqCst) {
+            return false;
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return true;
+        }
+        thread::sleep(remaining.min(Duration::from_millis(5)));
+    }
+}
+
+fn get_word_end_times(text: &TextToPlay, base_duration: f32, speed: f32, actions_length: &HashMap<char, (i32, i32)>) -> Vec<f32> { // of the main text words
+    let mut word_end_times = Vec::<f32>::new();
+    for timed_element in ElementWalk::new(&text.elements, &text.speed_pattern, base_duration, speed, actions_length) {
+        let (i, element) = (timed_element.position, timed_element.element);
+        if (element == '/' || element == '%') && text.is_main_text(i) {
+            word_end_times.push(timed_element.start);
+        }
+        if timed_element.action != -1 && element != '/' && element != '%' && text.main_text.iter().any(|range| !range.is_empty() && range.end == i + 1) { // ranges split by a periodic id end with a word gap
+            word_end_times.push(timed_element.start + timed_element.duration);
+        }
+    }
+    word_end_times
+}
+
+fn get_char_start_times(text: &TextToPlay, base_duration: f32, speed: f32, actions_length: &HashMap<char, (i32, i32)>) -> Vec<f32> { // first tones of the main text characters
+    let mut in_char = false;
+    ElementWalk::new(&text.elements, &text.speed_pattern, base_duration, speed, actions_length)
+        .filter(|timed_element| track_char(timed_element, text, &mut in_char))
+        .map(|timed_element| timed_element.start)
+        .collect()
+}
+
+fn get_element_start_times(text: &TextToPlay, base_duration: f32, speed: f32, actions_length: &HashMap<char, (i32, i32)>) -> Vec<f32> {
+    ElementWalk::new(&text.elements, &text.speed_pattern, base_duration, speed, actions_length)
+        .map(|timed_element| timed_element.start)
+        .collect()
+}
+
+fn get_samples_and_char_offsets(text: &TextToPlay, base_duration: f32, speed: f32, actions_length: &HashMap<char, (i32, i32)>, synth: &SynthSettings) -> Result<(usize, Vec<usize>), MorseError> { // same rounding as the synthesizer
+    ElementWalk::new(&text.elements, &text.speed_pattern, base_duration, speed, actions_length).check()?;
+    let mut char_sample_offsets = Vec::<usize>::new();
+    let mut samples: usize = 0;
+    let mut joined_units: i32 = 0; // tones joined into one wave, counted at its end
+    let mut dot_duration: f32 = get_dot_duration(base_duration, speed);
+    let mut in_char = false;
+    let tone_action = get_tone_action(synth);
+
+    for timed_element in ElementWalk::new(&text.elements, &text.speed_pattern, base_duration, speed, actions_length) {
+        let action = timed_element.action;
+        if action == -1 {
+            continue;
+        }
+        if action != tone_action || !synth.smooth_joins {
+            samples += get_samples_count(dot_duration * joined_units as f32);
+            joined_units = 0;
+        }
+        if track_char(&timed_element, text, &mut in_char) {
+            char_sample_offsets.push(samples);
+        }
+        if action == tone_action && synth.smooth_joins {
+            joined_units += timed_element.units;
+        } else if action != 2 {
+            samples += get_samples_count(timed_element.duration);
+        }
+        dot_duration = get_dot_duration(base_duration, timed_element.speed);
+    }
+    samples += get_samples_count(dot_duration * joined_units as f32);
+    Ok((samples, char_sample_offsets))
+}
+
+fn get_timeline(text: &TextToPlay, base_duration: f32, speed: f32, actions_length: &HashMap<char, (i32, i32)>, tone_action: i32) -> Result<Vec<MorseEvent>, MorseError> {
+    ElementWalk::new(&text.elements, &text.speed_pattern, base_duration, speed, actions_length).check()?;
+    let mut timeline = Vec::<MorseEvent>::new();
+    let mut char_index: Option<usize> = None;
+    let mut chars_count: usize = 0;
+    let mut in_char = false;
+
+    for timed_element in ElementWalk::new(&text.elements, &text.speed_pattern, base_duration, speed, actions_length) {
+        if track_char(&timed_element, text, &mut in_char) {
+            char_index = Some(chars_count);
+            chars_count += 1;
+        } else if !in_char {
+            char_index = None;
+        }
+        if timed_element.action != 2 && timed_element.action != -1 {
+            timeline.push(MorseEvent { start: timed_element.start, duration: timed_element.duration, tone: timed_element.action == tone_action, char_index });
+        }
+    }
+    Ok(timeline)
+}
+
+fn get_char_windows(timeline: &[MorseEvent]) -> Vec<(usize, f32, f32)> {
+    let mut char_windows = Vec::<(usize, f32, f32)>::new();
+    let mut after_char = false; // the pause right after a character belongs to its window
+    for event in timeline {
+        let end = event.start + event.duration;
+        match event.char_index {
+            Some(char_index) => {
+                match char_windows.last_mut() {
+                    Some(window) if window.0 == char_index => window.2 = end,
+                    _ => char_windows.push((char_index, event.start, end)),
+                }
+                after_char = true;
+            }
+            None => {
+                if let Some(window) = char_windows.last_mut().filter(|_| after_char && !event.tone) {
+                    window.2 = end;
+                }
+                after_char = false;
+            }
+        }
+    }
+    char_windows
+}
+
+fn has_morse_code(ch: char, generator: &GeneratorSettings) -> bool {
+    let ch = if generator.cut_numbers { *generator.cut_number_table.get(&ch).unwrap_or(&ch) } else { ch };
+    generator.code_table.contains_key(&ch)
+}
+
+fn get_unknown_chars(text: &[char], generator: &GeneratorSettings) -> Vec<char> {
+    text.iter()
+        .filter(|ch| !ch.is_whitespace() && !has_morse_code(**ch, generator))
+        .cloned()
+        .collect()
+}
+
+fn splice_periodic_id(text: TextToPlay, id: &[char], id_speed: f32, interval: f32, speed: f32, actions_length: &HashMap<char, (i32, i32)>) -> TextToPlay { // id isn't main text
+    let mut candidates = Vec::<(usize, f32, usize, f32)>::new(); // position after a word gap, time there, speed changes before it, speed there
+    let mut time: f32 = 0.0;
+    for timed_element in ElementWalk::new(&text.elements, &text.speed_pattern, text.base_duration, speed, actions_length) {
+        let i = timed_element.position;
+        time = timed_element.start + timed_element.duration;
+        if (timed_element.element == '/' || timed_element.element == '%') && text.is_main_text(i) && text.is_main_text(i + 1) {
+            candidates.push((i + 1, time, timed_element.speed_changes, timed_element.speed));
+        }
+    }
+
+    let mut chosen = Vec::<(usize, f32, usize, f32)>::new();
+    let mut next_time = interval;
+    let mut previous: Option<(usize, f32, usize, f32)> = None; // last word gap before next_time
+    for candidate in candidates {
+        if candidate.1 < next_time {
+            previous = Some(candidate);
+            continue;
+        }
+        let nearest = match previous {
+            Some(previous) if next_time - previous.1 < candidate.1 - next_time => previous,
+            _ => candidate,
+        };
+        chosen.push(nearest);
+        next_time = nearest.1 + interval;
+        previous = (nearest.0 != candidate.0).then_some(candidate);
+    }
+    if let Some(previous) = previous.filter(|_| next_time < time) { // the interval ends after the last word gap, but before the end
+        chosen.push(previous);
+    }
+
+    let mut elements = Vec::<char>::new();
+    let mut speed_pattern = Vec::<f32>::new();
+    let mut new_positions = Vec::<usize>::new(); // of the old elements, and of the end
+    let mut id_positions = Vec::<(usize, usize)>::new(); // old position and new position of every id
+    let mut pattern_position = 0;
+    let mut chosen = chosen.into_iter().peekable();
+    for i in 0..=text.elements.len() {
+        if let Some((position, _, char_now, speed_now)) = chosen.next_if(|candidate| candidate.0 == i) {
+            speed_pattern.extend(&text.speed_pattern[pattern_position..char_now]);
+            pattern_position = char_now;
+            id_positions.push((position, elements.len()));
+            elements.push('|');
+            speed_pattern.push(id_speed);
+            elements.extend(id);
+            elements.push('|');
+            speed_pattern.push(speed_now);
+            elements.push('/');
+        }
+        new_positions.push(elements.len());
+        if let Some(element) = text.elements.get(i) {
+            elements.push(*element);
+        }
+    }
+    speed_pattern.extend(&text.speed_pattern[pattern_position..]);
+
+    let mut main_text = Vec::<Range<usize>>::new();
+    for range in &text.main_text {
+        let mut start = new_positions[range.start];
+        for (position, id_position) in id_positions.iter().filter(|(position, _)| range.contains(position)) {
+            main_text.push(start..*id_position);
+            start = new_positions[*position];
+        }
+        main_text.push(start..new_positions[range.end]);
+    }
+    TextToPlay { elements, speed_pattern, main_text, ..text }
+}
+
+fn get_sent_chars(text: &[char], generator: &GeneratorSettings) -> Vec<char> { // the characters gen_audio_prev_vec sends, echoed words twice
+    let text: String = text.iter().collect();
+    let mut sent_chars = Vec::<char>::new();
+    for word in text.split_whitespace() {
+        let word: Vec<char> = word.chars()
+            .filter(|ch| match generator.unknown_policy {
+                UnknownPolicy::Substitute(substitute) => has_morse_code(*ch, generator) || generator.code_table.contains_key(&substitute),
+                _ => has_morse_code(*ch, generator),
+            })
+            .collect();
+        sent_chars.extend(&word);
+        if generator.word_echo {
+            sent_chars.extend(&word);
+        }
+    }
+    sent_chars
+}
+
+/*
+
+    KEY TIMELINE BYTES:
+        key_timeline_bytes encodes the key as a list of segments from the start of playing,
+        up to the end of the last tone. Every segment is one unsigned LEB128 varint
+        (7 bits per byte, lowest bits first, the highest bit set on all bytes but the last)
+        holding (milliseconds << 1) | state, where state 1 is key down and 0 key up.
+        Every tone is its own down segment, so two down segments follow each other
+        where there's no pause between tones. An up segment comes before a tone
+        whenever there's a pause, the first one too if playing starts with a pause.
+
+*/
+
+fn push_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn get_key_timeline(audio_prev_vec: &[char], base_duration: f32, speed: f32, speed_pattern: &[f32], actions_length: &HashMap<char, (i32, i32)>, tone_action: i32) -> Result<Vec<(f32, f32)>, MorseError> {
+    ElementWalk::new(audio_prev_vec, speed_pattern, base_duration, speed, actions_length).check()?;
+    Ok(ElementWalk::new(audio_prev_vec, speed_pattern, base_duration, speed, actions_length)
+        .filter(|timed_element| timed_element.action == tone_action)
+        .map(|timed_element| (timed_element.start, timed_element.duration))
+        .collect())
+}
+
+fn get_char_file_name(ch: char) -> String { // characters like '/' or '?' can't be used in file names
+    if ch.is_ascii_alphanumeric() {
+        format!("{}.wav", ch)
+    } else {
+        format!("U+{:04X}.wav", ch as u32)
+    }
+}
+
+fn write_wav(path: &Path, samples: &[f32], bit_depth: BitDepth) -> io::Result<()> {
+    write_wav_to(std::fs::File::create(path)?, samples, bit_depth)
+}
+
+fn write_wav_to<W: io::Write + io::Seek>(output: W, samples: &[f32], bit_depth: BitDepth) -> io::Result<()> {
+    let (bits_per_sample, sample_format) = match bit_depth {
+        BitDepth::Int16 => (16, hound::SampleFormat::Int),
+        BitDepth::Int24 => (24, hound::SampleFormat::Int),
+        BitDepth::Float32 => (32, hound::SampleFormat::Float),
+    };
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample,
+        sample_format,
+    };
+    let mut writer = hound::WavWriter::new(io::BufWriter::new(output), spec).map_err(io::Error::other)?;
+    for sample in samples {
+        let sample = sample.clamp(-1.0, 1.0);
+        match bit_depth {
+            BitDepth::Int16 => writer.write_sample((sample * i16::MAX as f32) as i16),
+            BitDepth::Int24 => writer.write_sample((sample * 8_388_607.0) as i32),
+            BitDepth::Float32 => writer.write_sample(sample),
+        }.map_err(io::Error::other)?;
+    }
+    writer.finalize().map_err(io::Error::other)
+}
+
+fn encode_base64(bytes: &[u8]) -> String { // standard alphabet with padding
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| group | (*byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gen_player(text: &str) -> AudioPlayer {
+        let mut player = AudioPlayer::new_headless();
+        player.set_text_str(text);
+        player.set_text_additions(TextAdditions::None);
+        player
+    }
+
+    #[test]
+    fn fade_fraction_is_validated() {
+        let mut player = gen_player("E");
+        assert_eq!(player.set_fade_mode(FadeMode::FractionOfDit(2.0)), Err(MorseError::InvalidFadeFraction(2.0)));
+        assert!(player.set_fade_mode(FadeMode::FractionOfDit(f32::NAN)).is_err());
+        assert!(player.set_fade_mode(FadeMode::FractionOfDit(-0.1)).is_err());
+        assert_eq!(player.set_fade_mode(FadeMode::FractionOfDit(0.5)), Ok(()));
+        assert!(!player.render_samples().unwrap().is_empty());
+    }
+
+    #[test]
+    fn element_units_are_validated() {
+        let mut player = gen_player("E");
+        assert_eq!(player.set_element_units('=', 0, 0), Err(MorseError::InvalidUnits(0)));
+        assert_eq!(player.set_element_units('=', 0, -1), Err(MorseError::InvalidUnits(-1)));
+        assert_eq!(player.set_element_units('=', 1, -1), Err(MorseError::InvalidUnits(-1)));
+        assert_eq!(player.set_element_units('=', 1, 0), Ok(()));
+        assert_eq!(player.set_element_units('|', 0, 1), Err(MorseError::InvalidElement('|')));
+        assert_eq!(player.set_element_units('=', 2, 1), Err(MorseError::InvalidAction(2)));
+    }
+
+    #[tokio::test]
+    async fn tone_duration_is_validated() {
+        let player = gen_player("E");
+        assert_eq!(player.play_tone(Duration::ZERO).await, Err(MorseError::InvalidDuration(Duration::ZERO)));
+        let too_long = Duration::from_secs_f32(MAX_WAVE_DURATION + 1.0);
+        assert_eq!(player.play_tone(too_long).await, Err(MorseError::InvalidDuration(too_long)));
+    }
+
+    #[tokio::test]
+    async fn raw_element_units_are_validated() {
+        let player = gen_player("E");
+        assert_eq!(player.play_raw_elements(&[(true, 0)]).await, Err(MorseError::InvalidUnits(0)));
+        assert_eq!(player.play_raw_elements(&[(true, 1), (false, -3)]).await, Err(MorseError::InvalidUnits(-3)));
+    }
+
+    #[test]
+    fn single_chars_ignore_word_echo() {
+        let mut player = gen_player("E");
+        let (_, plain) = player.gen_plain_prev_vec(&['A']);
+        player.set_word_echo(true, 0.5);
+        let (speed_pattern, echoed) = player.gen_plain_prev_vec(&['A']);
+        assert_eq!(echoed, plain);
+        assert_eq!(echoed, vec!['.', '*', '-']);
+        assert!(speed_pattern.is_empty());
+    }
+
+    #[test]
+    fn preamble_keeps_the_speed_pattern_in_step() {
+        let mut player = gen_player("EE TT");
+        player.set_text_additions(TextAdditions::Training);
+        player.set_preamble_text("E T");
+        player.set_emphasis_chars(&['E'], 0.5).unwrap();
+        let mut word_speeds = HashMap::new();
+        word_speeds.insert(0, 30.0);
+        player.set_word_speeds(word_speeds).unwrap();
+        let text_to_play = player.gen_text_to_play();
+        assert_eq!(text_to_play.elements.iter().filter(|element| **element == '|').count(), text_to_play.speed_pattern.len());
+        assert!(!player.render_samples().unwrap().is_empty());
+    }
+
+    #[test]
+    fn unknown_policy_error_covers_renders_and_timings() {
+        let mut player = gen_player("E{E");
+        player.set_unknown_char_policy(UnknownPolicy::Error);
+        assert_eq!(player.render_samples().unwrap_err(), MorseError::UnsupportedChar('{'));
+        assert_eq!(player.get_total_duration().unwrap_err(), MorseError::UnsupportedChar('{'));
+        assert!(player.get_key_timeline().is_err());
+        assert!(player.timeline().is_err());
+        assert!(player.rendered_duration().is_err());
+        assert_eq!(player.to_wav_data_uri().unwrap_err().kind(), io::ErrorKind::Other);
+        player.set_unknown_char_policy(UnknownPolicy::Drop);
+        assert!(player.render_samples().is_ok());
+    }
+
+    #[test]
+    fn wpm_is_validated() {
+        assert_eq!(get_speed_for_wpm(LETTERS_DURATION, 0.0), Err(MorseError::InvalidSpeed(0.0)));
+        assert_eq!(get_speed_for_wpm(LETTERS_DURATION, -5.0), Err(MorseError::InvalidSpeed(-5.0)));
+        assert!(get_speed_for_wpm(LETTERS_DURATION, f32::NAN).is_err());
+        assert!(get_speed_for_wpm(LETTERS_DURATION, f32::INFINITY).is_err());
+        let speed = get_speed_for_wpm(LETTERS_DURATION, 20.0).unwrap();
+        assert!((get_dot_duration(LETTERS_DURATION, speed) - 0.06).abs() < 1e-6); // 1.2 s / wpm
+    }
+
+    #[test]
+    fn word_speeds_are_validated() {
+        let mut player = gen_player("EE TT");
+        for speed in [0.0, -20.0, f32::INFINITY] {
+            assert_eq!(player.set_word_speeds(HashMap::from([(0, 30.0), (1, speed)])), Err(MorseError::InvalidSpeed(speed)));
+        }
+        assert!(player.set_word_speeds(HashMap::from([(1, f32::NAN)])).is_err());
+        assert!(player.generator.word_speeds.is_empty());
+        assert_eq!(player.set_word_speeds(HashMap::from([(1, 30.0)])), Ok(()));
+    }
+
+    #[test]
+    fn text_type_is_resolved() {
+        let text: Vec<char> = "AB 12".chars().collect();
+        assert!(get_text_type_for(TextType::Auto, &text) == TextType::Mixed);
+        assert!(get_text_type_for(TextType::Auto, &text[3..]) == TextType::Digits);
+        assert!(get_text_type_for(TextType::Auto, &text[..2]) == TextType::Letters);
+        assert!(get_text_type_for(TextType::Auto, &['?']) == TextType::Letters);
+        assert!(get_text_type_for(TextType::Digits, &text[..2]) == TextType::Digits);
+    }
+
+    #[test]
+    fn base_duration_follows_the_text_type() {
+        assert_eq!(get_base_duration_for(TextType::Letters, 1.0, 2.0, 3.0), 1.0);
+        assert_eq!(get_base_duration_for(TextType::Digits, 1.0, 2.0, 3.0), 2.0);
+        assert_eq!(get_base_duration_for(TextType::Mixed, 1.0, 2.0, 3.0), 3.0);
+    }
+
+    #[test]
+    fn start_speed_is_the_edge_speed() {
+        assert_eq!(get_start_speed_for(SpeedModificationType::None, 100.0, 80.0, 120.0), 100.0);
+        assert_eq!(get_start_speed_for(SpeedModificationType::Speedup, 100.0, 80.0, 120.0), 80.0);
+        assert_eq!(get_start_speed_for(SpeedModificationType::Zigzag, 100.0, 80.0, 120.0), 80.0);
+        assert_eq!(get_start_speed_for(SpeedModificationType::Slowing, 100.0, 80.0, 120.0), 120.0);
+    }
+
+    #[test]
+    fn settings_match_the_player() {
+        let mut player = gen_player("AB 12");
+        player.set_text_type(TextType::Auto);
+        player.set_modification(SpeedModificationType::Slowing);
+        let settings = player.get_settings();
+        assert_eq!(settings.get_text_duration("AB 12"), player.get_text_duration());
+    }
+
+    #[test]
+    fn inverted_timings_follow_the_sound() {
+        let mut player = gen_player("A");
+        let keyed = player.get_key_timeline().unwrap();
+        let plain = player.timeline().unwrap();
+        player.set_inverted(true);
+        let inverted = player.timeline().unwrap();
+        assert_eq!(keyed.len(), 2);
+        assert_eq!(player.get_key_timeline().unwrap().len(), plain.iter().filter(|event| !event.tone).count());
+        for (plain, inverted) in plain.iter().zip(&inverted) {
+            assert_eq!(inverted.tone, !plain.tone);
+            assert_eq!(inverted.char_index, plain.char_index);
+        }
+    }
+
+    #[test]
+    fn emphasis_steps_the_speed_like_the_synthesizer() {
+        let mut player = gen_player("TEE");
+        player.set_emphasis_chars(&['E'], 0.5).unwrap();
+        let tones: Vec<f32> = player.timeline().unwrap().iter().filter(|event| event.tone).map(|event| event.duration).collect();
+        assert_eq!(tones.len(), 3);
+        assert!((tones[1] - tones[0] * 2.0 / 3.0).abs() < 1e-6); // a dot at half the speed is 2/3 of a dash
+        assert_eq!(tones[1], tones[2]);
+        assert_eq!(player.rendered_duration().unwrap(), Duration::from_secs_f64(player.render_samples().unwrap().len() as f64 / SAMPLE_RATE as f64));
+    }
+
+    #[test]
+    fn bandwidth_grows_with_speed() {
+        let mut player = gen_player("PARIS");
+        player.set_speed(50.0);
+        let slow = player.estimate_bandwidth_hz();
+        player.set_speed(300.0);
+        let fast = player.estimate_bandwidth_hz();
+        assert!(fast > slow);
+        assert!(slow > 1.0 / FADE_OUT);
+
+        player.set_fade_mode(FadeMode::FractionOfDit(0.1)).unwrap();
+        let fast = player.estimate_bandwidth_hz();
+        player.set_speed(150.0);
+        let half = player.estimate_bandwidth_hz();
+        assert!((fast / half - 2.0).abs() < 1e-3); // both terms scale with the dit
+        player.set_fade_mode(FadeMode::FractionOfDit(0.0)).unwrap();
+        assert!(player.estimate_bandwidth_hz().is_finite());
+    }
+
+    #[test]
+    fn clipping_includes_the_noise() {
+        let mut player = gen_player("E");
+        player.set_volume(1.0).unwrap();
+        player.set_output_gain(0.9).unwrap();
+        assert_eq!(player.would_clip(), Ok(false));
+        player.set_noise(Some(0.0), NoiseType::White).unwrap();
+        assert_eq!(player.would_clip(), Ok(true));
+    }
+
+    #[tokio::test]
+    async fn play_report_counts_tones_and_pauses() {
+        let mut player = gen_player("E E");
+        player.set_speed(400.0);
+        player.set_emphasis_chars(&['E'], 2.0).unwrap();
+        player.set_pad(0.01, 0.01);
+        player.play().await.unwrap();
+        let text_to_play = player.gen_text_to_play();
+        let report = player.last_play_report().unwrap();
+        assert!(report.completed);
+        assert_eq!(report.element_count, count_elements(&text_to_play.elements, &player.get_element_table()));
+        assert!(report.element_count < text_to_play.elements.len());
+    }
+
+    #[test]
+    fn waves_are_cached_per_speed() {
+        let mut player = gen_player("EEEE");
+        player.set_emphasis_chars(&['E'], 0.5).unwrap();
+        let mut synthesizer = player.gen_synthesizer(player.gen_text_to_play(), player.get_start_speed()).unwrap();
+        while synthesizer.next_block().is_some() {}
+        assert_eq!(synthesizer.sounds_by_speed.len(), 1);
+        assert!(!player.get_actions_length().contains_key(&'_'));
+    }
+
+    #[test]
+    fn fixed_pauses_are_settings_of_their_own() {
+        let mut player = gen_player("E");
+        player.set_repeat(2, 0.0);
+        let without_gap = player.get_total_duration().unwrap();
+        player.set_repeat(2, 0.5);
+        player.set_pad(0.25, 0.0);
+        assert!((player.get_total_duration().unwrap() - without_gap - 0.75).abs() < 1e-5);
+        let actions_length = player.get_actions_length();
+        assert!(['~', '#', '^', '<', '>'].iter().all(|element| !actions_length.contains_key(element)));
+        assert_eq!(player.get_element_table()[&'~'], (3, 500));
+    }
+
+    #[test]
+    fn group_gap_can_be_turned_off() {
+        let mut player = gen_player("ABC DEF");
+        let word_gap = player.get_total_duration().unwrap();
+        player.set_group_gap(Some(14));
+        assert!(player.gen_text_to_play().elements.contains(&'%'));
+        assert!(player.get_total_duration().unwrap() > word_gap);
+        player.set_group_gap(None);
+        assert!(!player.gen_text_to_play().elements.contains(&'%'));
+        assert_eq!(player.get_total_duration().unwrap(), word_gap);
+        for element in RESERVED_ELEMENTS {
+            assert_eq!(player.set_element_units(*element, 1, 1), Err(MorseError::InvalidElement(*element)));
+        }
+    }
+
+    #[test]
+    fn announcement_gap_is_between_the_digits() {
+        let mut player = gen_player("E");
+        player.set_speed(124.5);
+        let digits = |gap: char| -> Vec<char> {
+            [".----", "..---", "....."].iter()
+                .map(|code| code.chars().flat_map(|element| ['*', element]).skip(1).collect::<Vec<char>>())
+                .collect::<Vec<Vec<char>>>()
+                .join(&gap)
+        };
+        assert_eq!(player.render_announcement(), digits('$'));
+        player.set_announcement_gap(Some(6));
+        assert_eq!(player.render_announcement(), digits('!'));
+        player.set_announcement_gap(None);
+        assert_eq!(player.render_announcement(), digits('$'));
+    }
+
+    #[tokio::test]
+    async fn play_awaits_the_previous_producer() {
+        let mut player = gen_player("PARIS PARIS");
+        player.set_speed(400.0);
+        let first = player.spawn_play().unwrap();
+        assert!(player.producer.is_running());
+        player.set_text_str("E");
+        player.play().await.unwrap();
+        first.await;
+        assert!(!player.producer.is_running());
+        assert!(player.last_play_report().unwrap().completed);
+    }
+
+    #[test]
+    fn rendered_duration_matches_render_samples() {
+        let mut player = gen_player("CQ DE AB1CD 5NN");
+        player.set_text_additions(TextAdditions::Training);
+        player.set_modification(SpeedModificationType::Speedup);
+        player.set_smooth_joins(true);
+        player.set_repeat(2, 0.3);
+        player.set_pad(0.1, 0.2);
+        let samples = player.render_samples().unwrap();
+        assert_eq!(player.rendered_duration().unwrap(), Duration::from_secs_f64(samples.len() as f64 / SAMPLE_RATE as f64));
+    }
+
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>); // a Vec<u8> the test can read after the player wrote into it
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn echo_writer_gets_the_sent_characters() {
+        let mut player = gen_player("ab c{d");
+        player.set_speed(400.0);
+        let echo = Arc::new(Mutex::new(Vec::<u8>::new()));
+        player.set_echo_writer(Box::new(SharedBuffer(echo.clone())));
+        player.play().await.unwrap();
+        assert_eq!(String::from_utf8(echo.lock().unwrap().clone()).unwrap(), "AB CD");
+    }
+
+    #[test]
+    fn delay_sets_both_gaps_from_the_next_play_on() {
+        let mut player = gen_player("E E");
+        let samples = player.render_samples().unwrap();
+        let text_duration = player.get_text_duration().unwrap();
+        let mut synthesizer = player.gen_synthesizer(player.gen_text_to_play(), player.get_start_speed()).unwrap();
+        player.set_delay(6);
+        let actions_length = player.get_actions_length();
+        assert_eq!(actions_length[&'$'], (1, 6));
+        assert_eq!(actions_length[&'/'], (1, 13));
+        assert!(player.get_text_duration().unwrap() > text_duration);
+        let mut playing = Vec::<f32>::new(); // built before set_delay, as a play started earlier
+        while let Some(sound_signal) = synthesizer.next_block() {
+            playing.extend(sound_signal);
+        }
+        assert_eq!(playing.len(), samples.len());
+        assert!(player.render_samples().unwrap().len() > samples.len());
+    }
+
+    #[test]
+    fn wav_bit_depths_read_back() {
+        let samples = [0.0, 0.5, -0.5, 1.0, -1.0, 0.25];
+        for (bit_depth, bits_per_sample, sample_format) in [(BitDepth::Int16, 16, hound::SampleFormat::Int),
+            (BitDepth::Int24, 24, hound::SampleFormat::Int), (BitDepth::Float32, 32, hound::SampleFormat::Float)] {
+            let mut wav = Cursor::new(Vec::new());
+            write_wav_to(&mut wav, &samples, bit_depth).unwrap();
+            wav.set_position(0);
+            let mut reader = hound::WavReader::new(wav).unwrap();
+            let spec = reader.spec();
+            assert_eq!((spec.channels, spec.sample_rate, spec.bits_per_sample, spec.sample_format), (1, SAMPLE_RATE, bits_per_sample, sample_format));
+            let read: Vec<f32> = match sample_format {
+                hound::SampleFormat::Int => {
+                    let full_scale = ((1_i32 << (bits_per_sample - 1)) - 1) as f32;
+                    reader.samples::<i32>().map(|sample| sample.unwrap() as f32 / full_scale).collect()
+                }
+                hound::SampleFormat::Float => reader.samples::<f32>().map(Result::unwrap).collect(),
+            };
+            assert_eq!(read.len(), samples.len());
+            for (read, sample) in read.iter().zip(samples) {
+                assert!((read - sample).abs() <= 1.0 / 32767.0, "{} read back as {}", sample, read);
+            }
+        }
+    }
+
+    #[test]
+    fn line_breaks_and_tabs_separate_words() {
+        let (_, spaced) = gen_player("CQ DE AB1CD K").gen_main_text();
+        let (_, multi_line) = gen_player("CQ\nDE\r\nAB1CD\tK").gen_main_text();
+        assert_eq!(multi_line, spaced);
+        assert_eq!(multi_line.iter().filter(|element| **element == '/').count(), 3);
+    }
+
+    #[test]
+    fn paris_elements_and_dits() {
+        let player = gen_player("PARIS");
+        assert_eq!(player.element_count(), 27);
+        assert_eq!(player.dit_count(), 43);
+        let player = gen_player("PARIS PARIS");
+        assert_eq!(player.element_count(), 55);
+        assert_eq!(player.dit_count(), 93);
+    }
+
+    #[test]
+    fn elements_missing_from_the_table_are_skipped() {
+        let player = gen_player("EE");
+        let actions_length = player.get_element_table();
+        let (duration, timings) = get_time_and_timings(&['.', '$', '.'], 0.05, 100.0, &[], &actions_length).unwrap();
+        assert_eq!(get_time_and_timings(&['.', '\u{7}', '$', '.'], 0.05, 100.0, &[], &actions_length), Ok((duration, timings)));
+        assert_eq!(get_key_timeline(&['.', '\u{7}', '$', '.'], 0.05, 100.0, &[], &actions_length, 0).unwrap().len(), 2);
+
+        let render = |actions_length: HashMap<char, (i32, i32)>| {
+            let mut synthesizer = Synthesizer::new(player.gen_text_to_play(), 0.05, 100.0, actions_length, player.synth.clone()).unwrap();
+            let mut samples = Vec::<f32>::new();
+            while let Some(sound_signal) = synthesizer.next_block() {
+                samples.extend(sound_signal);
+            }
+            samples.len()
+        };
+        let mut without_gap = actions_length.clone();
+        without_gap.remove(&'$');
+        let mut zero_gap = actions_length;
+        zero_gap.insert('$', (1, 0));
+        assert_eq!(render(without_gap), render(zero_gap));
+    }
+
+    #[test]
+    fn whitespace_runs_are_one_word_gap() {
+        let (_, single) = gen_player("A B").gen_main_text();
+        assert_eq!(single.iter().filter(|element| **element == '/').count(), 1);
+        assert_eq!(gen_player("A  B").gen_main_text().1, single);
+        assert_eq!(gen_player(" A \t B ").gen_main_text().1, single);
+        let (_, letter) = gen_player("A").gen_main_text();
+        assert_eq!(gen_player(" A").gen_main_text().1, letter);
+        assert_eq!(gen_player("A ").gen_main_text().1, letter);
+        assert!(gen_player("  ").gen_main_text().1.is_empty());
+    }
+
+    #[test]
+    fn run_together_signs_have_no_character_gap() {
+        for (sign, code) in [('&', ".-..."), ('+', ".-.-."), ('(', "-.--.")] {
+            let (_, elements) = gen_player(&sign.to_string()).gen_main_text();
+            let expected: Vec<char> = code.chars().flat_map(|element| ['*', element]).skip(1).collect();
+            assert_eq!(elements, expected, "{}", sign);
+            assert_eq!(morse_to_text(code), sign.to_string());
+        }
+        let (_, elements) = gen_player("WAIT &").gen_main_text();
+        let sign_start = elements.iter().rposition(|element| *element == '/').unwrap() + 1;
+        assert!(!elements[sign_start..].contains(&'$'));
+    }
+
+    #[test]
+    fn difficulty_matches_hand_computed_values() {
+        let settings = PlayerSettings::default();
+        // E: 1 element in 1 dit, 1 char words, rarity 0 -> 1.0
+        // T: 1 element in 3 dits, 1 char words, rarity 1/26 -> 27/78
+        assert!((relative_difficulty("E", "T", &settings) - 78.0 / 27.0).abs() < 1e-5);
+        // EE: 3 elements in 5 dits, 2 char words, rarity 0 -> 1.2
+        assert!((relative_difficulty("EE", "E", &settings) - 1.2).abs() < 1e-5);
+        // E E: 3 elements in 9 dits, 1 char words, rarity 0 -> 1/3
+        assert!((relative_difficulty("E E", "e", &settings) - 1.0 / 3.0).abs() < 1e-5);
+        assert_eq!(relative_difficulty("", "", &settings), 1.0);
+        assert_eq!(relative_difficulty("E", " ", &settings), f32::INFINITY);
+    }
+
+    #[test]
+    fn char_code_is_validated() {
+        let mut player = gen_player("E");
+        assert_eq!(player.set_char_code('X', ".*-"), Err(MorseError::InvalidElement('*')));
+        assert_eq!(player.set_char_code('X', "._"), Err(MorseError::InvalidElement('_')));
+        assert_eq!(player.set_char_code('X', ""), Err(MorseError::UnsupportedChar('X')));
+        assert_eq!(player.set_char_code(' ', "."), Err(MorseError::UnsupportedChar(' ')));
+        assert_eq!(player.set_char_code('X', "-..-"), Ok(()));
+    }
+
+    #[test]
+    fn custom_tone_sounds_in_a_char_code() {
+        let mut player = gen_player("X");
+        player.set_element_units('_', 0, 5).unwrap();
+        player.set_char_code('X', "_").unwrap();
+        let dot = gen_player("E").get_key_timeline().unwrap()[0].1;
+        let key_timeline = player.get_key_timeline().unwrap();
+        assert_eq!(key_timeline.len(), 1);
+        assert!((key_timeline[0].1.as_secs_f32() - dot.as_secs_f32() * 5.0).abs() < 1e-6);
+
+        let long_dash = player.render_samples().unwrap().len();
+        let dash = gen_player("T").render_samples().unwrap().len();
+        let dot = gen_player("E").render_samples().unwrap().len();
+        assert!(long_dash.abs_diff(dash).abs_diff(dash - dot) <= 1); // each step adds two units
+    }
+
+    #[test]
+    fn extreme_low_speeds_are_rejected() {
+        let mut player = gen_player("E E");
+        player.set_speed(0.0);
+        assert_eq!(player.get_key_timeline(), Err(MorseError::InvalidSpeed(0.0)));
+        assert!(player.char_windows().is_err());
+        assert!(player.render_samples().is_err());
+        player.set_speed(0.01); // a 1500 s dash
+        assert!(matches!(player.get_total_duration(), Err(MorseError::InvalidDuration(_))));
+        assert!(matches!(player.rendered_duration(), Err(MorseError::InvalidDuration(_))));
+        assert!(player.get_settings().get_text_duration("E E").is_err());
+
+        player.set_speed(1.0); // 5 s dots and a 35 s word gap still fit in a wave
+        let total_duration = player.get_total_duration().unwrap();
+        let samples = player.render_samples().unwrap().len();
+        assert!((samples as f32 / SAMPLE_RATE as f32 - total_duration).abs() < 0.001);
+    }
+
+    #[test]
+    fn emphasis_factor_is_validated() {
+        let mut player = gen_player("E");
+        assert_eq!(player.set_emphasis_chars(&['E'], 0.0), Err(MorseError::InvalidSpeed(0.0)));
+        assert_eq!(player.set_emphasis_chars(&['E'], -0.5), Err(MorseError::InvalidSpeed(-0.5)));
+        assert!(player.set_emphasis_chars(&['E'], f32::NAN).is_err());
+        assert!(player.set_emphasis_chars(&['E'], f32::INFINITY).is_err());
+        assert!(player.get_key_timeline().is_ok());
+        assert_eq!(player.set_emphasis_chars(&['E'], 0.5), Ok(()));
+    }
+
+    #[test]
+    fn negative_pre_text_gap_is_no_gap() {
+        let mut player = gen_player("E");
+        player.set_text_additions(TextAdditions::Training);
+        let key_timeline = player.get_key_timeline().unwrap();
+        player.set_pre_text_gap(-10.0);
+        assert_eq!(player.get_key_timeline().unwrap(), key_timeline);
+        assert!(player.char_windows().is_ok());
+    }
+
+    #[test]
+    fn base_durations_are_validated() {
+        let mut player = gen_player("E");
+        assert_eq!(player.set_base_durations(-0.05, 0.034, 0.042), Err(MorseError::InvalidBaseDuration(-0.05)));
+        assert_eq!(player.set_base_durations(0.05, 0.0, 0.042), Err(MorseError::InvalidBaseDuration(0.0)));
+        assert!(player.set_base_durations(0.05, 0.034, f32::NAN).is_err());
+        assert!(player.get_key_timeline().is_ok());
+        assert_eq!(player.set_base_durations(0.06, 0.04, 0.05), Ok(()));
+        assert!((player.get_key_timeline().unwrap()[0].1.as_secs_f32() - 0.06).abs() < 1e-6);
+    }
+
+    #[test]
+    fn negative_repeat_gap_is_no_gap() {
+        let mut player = gen_player("PARIS");
+        player.set_repeat(3, 0.0);
+        let total_duration = player.get_total_duration().unwrap();
+        player.set_repeat(3, -1.5);
+        assert_eq!(player.get_total_duration().unwrap(), total_duration);
+        assert!((player.rendered_duration().unwrap().as_secs_f32() - total_duration).abs() < 0.001);
+    }
+
+    #[test]
+    fn negative_echo_gap_is_no_gap() {
+        let mut player = gen_player("CQ DE AB1CD");
+        player.set_word_echo(true, 0.0);
+        let total_duration = player.get_total_duration().unwrap();
+        player.set_word_echo(true, -0.5);
+        assert_eq!(player.get_total_duration().unwrap(), total_duration);
+        assert!((player.rendered_duration().unwrap().as_secs_f32() - total_duration).abs() < 0.001);
+    }
+
+    #[test]
+    fn renders_need_morse_text() {
+        let mut player = gen_player("€€");
+        player.set_text_additions(TextAdditions::Training);
+        assert_eq!(player.render_samples(), Err(MorseError::NoMorseText));
+        assert_eq!(player.render_samples_deterministic(), Err(MorseError::NoMorseText));
+        assert_eq!(player.rendered_duration(), Err(MorseError::NoMorseText));
+        assert_eq!(player.render_into(&mut |_| {}), Err(MorseError::NoMorseText));
+        assert!(matches!(MorseSource::new(&player), Err(MorseError::NoMorseText)));
+        assert_eq!(player.to_wav_data_uri().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn raw_elements_replace_the_current_playing() {
+        let mut player = gen_player("PARIS PARIS PARIS");
+        player.set_speed(400.0);
+        let handle = player.spawn_play().unwrap();
+        let started_at = Instant::now();
+        player.play_raw_elements(&[(true, 3), (false, 1), (true, 1)]).await.unwrap();
+        assert!(started_at.elapsed() < Duration::from_secs(1));
+        handle.await;
+        assert!(!player.last_play_report().unwrap().completed);
+    }
+
+    #[tokio::test]
+    async fn tone_replaces_the_current_playing() {
+        let mut player = gen_player("PARIS PARIS PARIS");
+        player.set_speed(400.0);
+        let handle = player.spawn_play().unwrap();
+        let started_at = Instant::now();
+        player.play_tone(Duration::from_millis(100)).await.unwrap();
+        assert!(started_at.elapsed() < Duration::from_secs(1));
+        handle.await;
+        assert!(!player.last_play_report().unwrap().completed);
+    }
+
+    #[test]
+    fn rendered_duration_drifts_from_get_total_duration() {
+        let mut player = gen_player(&["PARIS"; 200].join(" "));
+        player.set_speed(123.0); // 1951.2 samples a dot
+        let total_duration = player.get_total_duration().unwrap() as f64;
+        let rendered_duration = player.rendered_duration().unwrap().as_secs_f64();
+        let elements = player.gen_text_to_play().elements.len() as f64;
+        let drift = total_duration - rendered_duration; // every element is cut to whole samples, about 33 ms over these 5599 elements
+        assert!(drift > 0.01);
+        assert!(drift <= elements / SAMPLE_RATE as f64, "{} s drift over {} elements", drift, elements);
+    }
+
+    #[test]
+    fn harmonics_match_the_per_harmonic_sum() {
+        let t_wave = Array1::linspace(0.0, 2.0, 2 * SAMPLE_RATE as usize);
+        let square: Vec<(u32, f32)> = (0..HARMONICS_COUNT).map(|harmonic| (2 * harmonic + 1, 1.0 / (2 * harmonic + 1) as f32)).collect();
+        let triangle: Vec<(u32, f32)> = (0..HARMONICS_COUNT).map(|harmonic| (2 * harmonic + 1, (-1.0_f32).powi(harmonic as i32) / ((2 * harmonic + 1).pow(2)) as f32)).collect();
+        let sawtooth: Vec<(u32, f32)> = (1..HARMONICS_COUNT).map(|harmonic| (harmonic, 1.0 / harmonic as f32)).collect();
+        for harmonics in [square, triangle, sawtooth] {
+            let wave = get_harmonics_wave(&t_wave, 700.0, &harmonics, false);
+            for (t, sample) in t_wave.iter().zip(wave.iter()) {
+                let expected: f64 = harmonics.iter()
+                    .map(|(multiplier, amplitude)| *amplitude as f64 * (2.0 * std::f64::consts::PI * 700.0 * *multiplier as f64 * *t as f64).sin())
+                    .sum();
+                assert!((*sample as f64 - expected).abs() < 1e-5, "{} instead of {} at {} s", sample, expected, t);
+            }
+        }
+    }
+
+    #[test]
+    fn lowpass_cutoff_is_validated() {
+        let mut player = gen_player("E");
+        for cutoff_hz in [f32::NAN, f32::INFINITY, 0.0, -100.0, 24000.0] {
+            assert!(matches!(player.set_lowpass(Some(cutoff_hz)), Err(MorseError::InvalidFrequency(_)))); // NaN isn't equal to itself
+        }
+        assert_eq!(player.set_lowpass(Some(1000.0)), Ok(()));
+        assert_eq!(player.set_lowpass(None), Ok(()));
+        assert!(player.render_samples().unwrap().iter().all(|sample| sample.is_finite()));
+    }
+
+    #[test]
+    fn output_gain_is_validated() {
+        let mut player = gen_player("E");
+        for gain in [f32::NAN, f32::INFINITY, -0.5] {
+            assert!(matches!(player.set_output_gain(gain), Err(MorseError::InvalidGain(_))));
+        }
+        assert_eq!(player.set_output_gain(0.0), Ok(()));
+        assert!(player.render_samples().unwrap().iter().all(|sample| *sample == 0.0));
+    }
+
+    #[test]
+    fn marker_volume_is_validated() {
+        let mut player = gen_player("E");
+        player.set_text_additions(TextAdditions::Training);
+        for factor in [f32::NAN, f32::NEG_INFINITY, -1.0] {
+            assert!(matches!(player.set_marker_volume(factor), Err(MorseError::InvalidGain(_))));
+        }
+        assert_eq!(player.set_marker_volume(0.5), Ok(()));
+        assert!(player.render_samples().unwrap().iter().all(|sample| sample.is_finite()));
+    }
+
+    #[test]
+    fn binaural_beat_is_validated() {
+        let mut player = gen_player("E");
+        for beat_hz in [f32::NAN, f32::INFINITY, -4.0, 24000.0] {
+            assert!(matches!(player.set_binaural(beat_hz), Err(MorseError::InvalidFrequency(_))));
+        }
+        assert_eq!(player.set_binaural(4.0), Ok(()));
+        assert_eq!(player.set_binaural(0.0), Ok(()));
+    }
+
+    #[test]
+    fn noise_level_is_validated() {
+        let mut player = gen_player("E");
+        for snr_db in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            assert!(matches!(player.set_noise(Some(snr_db), NoiseType::White), Err(MorseError::InvalidDecibels(_))));
+        }
+        assert_eq!(player.set_noise(Some(-10.0), NoiseType::Pink), Ok(()));
+        assert!(player.render_samples().unwrap().iter().all(|sample| sample.is_finite()));
+        assert_eq!(player.set_noise(None, NoiseType::White), Ok(()));
+    }
+
+    #[test]
+    fn volume_db_is_validated() {
+        let mut player = gen_player("E");
+        for db in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            assert!(matches!(player.set_volume_db(db), Err(MorseError::InvalidDecibels(_))));
+        }
+        assert_eq!(player.set_volume_db(-6.0), Ok(()));
+        assert!((player.volume_db().unwrap() + 6.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn fades_longer_than_the_tone_are_shortened() {
+        let mut samples = Array1::from_elem(10, 1.0_f32);
+        apply_hann_window(&mut samples, 30, 15);
+        assert_eq!(samples[0], 0.0);
+        assert_eq!(samples[9], 0.0);
+    }
 }
\ No newline at end of file
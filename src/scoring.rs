@@ -0,0 +1,107 @@
+const STANDARD_WORD_LENGTH: usize = 5; // characters, the word of speed ratings and code groups
+
+/*
+
+    COPY SCORING:
+        Both texts are uppercased and split into words at whitespace, so case and the
+        amount of spaces don't matter. edit_distance is the Levenshtein distance between
+        the characters of the texts without spaces: every inserted, deleted or substituted
+        character costs 1, so extra characters in the copy lose as much as missing ones.
+        char_accuracy is 1 - edit_distance / sent characters, not below 0.0. word_accuracy
+        is the same over words, a word is either copied exactly or it costs 1. Like in
+        contests, errors_per_word counts the errors per 5 sent characters, so texts with
+        long or short words compare fairly. An empty sent text scores 1.0 against an empty
+        copy and 0.0 against anything else.
+
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CopyScore {
+    pub char_accuracy: f32, // 0.0..=1.0
+    pub word_accuracy: f32, // 0.0..=1.0
+    pub edit_distance: usize, // characters, spaces not counted
+    pub errors_per_word: f32, // edit_distance per 5 sent characters
+}
+
+pub fn score_copy(sent: &str, received: &str) -> CopyScore { // see COPY SCORING
+    let sent_words = get_words(sent);
+    let received_words = get_words(received);
+    let sent_chars: Vec<char> = sent_words.concat();
+    let received_chars: Vec<char> = received_words.concat();
+    let edit_distance = get_edit_distance(&sent_chars, &received_chars);
+    let word_distance = get_edit_distance(&sent_words, &received_words);
+    CopyScore {
+        char_accuracy: get_accuracy(edit_distance, sent_chars.len(), received_chars.is_empty()),
+        word_accuracy: get_accuracy(word_distance, sent_words.len(), received_words.is_empty()),
+        edit_distance,
+        errors_per_word: match sent_chars.len() {
+            0 => edit_distance as f32,
+            sent_count => edit_distance as f32 * STANDARD_WORD_LENGTH as f32 / sent_count as f32,
+        },
+    }
+}
+
+fn get_words(text: &str) -> Vec<Vec<char>> {
+    text.split_whitespace().map(|word| word.chars().flat_map(|ch| ch.to_uppercase()).collect()).collect()
+}
+
+fn get_accuracy(distance: usize, sent_count: usize, nothing_received: bool) -> f32 {
+    if sent_count == 0 {
+        return if nothing_received { 1.0 } else { 0.0 };
+    }
+    (1.0 - distance as f32 / sent_count as f32).max(0.0)
+}
+
+fn get_edit_distance<T: PartialEq>(a: &[T], b: &[T]) -> usize { // Levenshtein, one row at a time
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, item_a) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, item_b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(item_a != item_b);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inserted_character_costs_one() {
+        let score = score_copy("PARIS", "PARRIS");
+        assert_eq!(score.edit_distance, 1);
+        assert_eq!(score.char_accuracy, 0.8);
+        assert_eq!(score.word_accuracy, 0.0);
+        assert_eq!(score.errors_per_word, 1.0);
+    }
+
+    #[test]
+    fn deleted_characters_cost_one_each() {
+        let score = score_copy("paris test", "PRIS  TST");
+        assert_eq!(score.edit_distance, 2);
+        assert_eq!(score.char_accuracy, 1.0 - 2.0 / 9.0);
+        assert_eq!(score.word_accuracy, 0.0);
+    }
+
+    #[test]
+    fn word_boundaries_count_for_words_only() {
+        let joined = score_copy("CQ DE AB1CD", "CQDE AB1CD");
+        assert_eq!(joined.edit_distance, 0);
+        assert_eq!(joined.char_accuracy, 1.0);
+        assert_eq!(joined.word_accuracy, 1.0 - 2.0 / 3.0);
+        let split = score_copy("CQ DE AB1CD", "CQ DE AB 1CD");
+        assert_eq!(split.edit_distance, 0);
+        assert_eq!(split.word_accuracy, 1.0 - 2.0 / 3.0);
+    }
+
+    #[test]
+    fn empty_sent_text() {
+        assert_eq!(score_copy("", " ").char_accuracy, 1.0);
+        assert_eq!(score_copy("", "E").char_accuracy, 0.0);
+        assert_eq!(score_copy("", "E").errors_per_word, 1.0);
+    }
+}
\ No newline at end of file